@@ -70,12 +70,14 @@ fn main() {
                 timeout_delta: Duration::seconds(1),
                 discovery_addr: SocketAddr::from(([0, 0, 0, 0], sd_port)),
                 seeking_addr: SocketAddr::from(([0, 0, 0, 0], CONST_SERVICE_DISCOVERY_PORT)),
+                ..Default::default()
             }
         } else {
             MulticastServiceDiscoveryConfig {
                 timeout_delta: Duration::seconds(1),
                 discovery_addr: SocketAddr::from(([0, 0, 0, 0], CONST_SERVICE_DISCOVERY_PORT)),
                 seeking_addr: SocketAddr::from(([0, 0, 0, 0], sd_port)),
+                ..Default::default()
             }
         }
     };
@@ -4,7 +4,7 @@ use crate::service_discovery::mdns::prelude::*;
 
 use lightproc::prelude::*;
 
-use futures::{select, FutureExt};
+use futures::{select, FutureExt, StreamExt};
 use pin_utils::pin_mut;
 use std::{cell::Cell, sync::Arc};
 use uuid::Uuid;
@@ -74,12 +74,12 @@ impl ArtilleryAPCluster {
     }
 
     async fn discover_nodes(&self) {
-        self.service_discovery()
-            .events()
-            .iter()
-            .filter(|discovery| {
-                discovery.get().port() != self.config.sd_config.local_service_addr.port()
-            })
-            .for_each(|discovery| self.cluster.add_seed_node(discovery.get()))
+        let mut discoveries = self.service_discovery().events();
+
+        while let Some(discovery) = discoveries.next().await {
+            if discovery.get().port() != self.config.sd_config.local_service_addr.port() {
+                self.cluster.add_seed_node(discovery.get());
+            }
+        }
     }
 }
@@ -10,3 +10,12 @@ pub const CONST_INFECTION_PORT: u16 = 27845;
 // Behave like this is the size. Normally 512 is enough.
 /// Default UDP cast packet size
 pub const CONST_PACKET_SIZE: usize = 1 << 16;
+
+/// Upper bound of a frame exchanged over the epidemic TCP streams
+pub const CONST_STREAM_FRAME_SIZE: usize = 1 << 24;
+
+/// Epidemic wire protocol version spoken by this build
+pub const CONST_PROTOCOL_VERSION: u8 = 1;
+
+/// Oldest epidemic wire protocol version this build understands
+pub const CONST_MIN_PROTOCOL_VERSION: u8 = 1;
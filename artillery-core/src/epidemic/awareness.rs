@@ -0,0 +1,39 @@
+use chrono::Duration;
+use std::convert::TryFrom;
+
+///
+/// Lifeguard local health awareness.
+///
+/// Failed probes and refuted suspicions about ourselves raise the health score,
+/// successful probes lower it. A high score means that we are likely the slow
+/// node, so our probe intervals and timeouts get stretched accordingly instead of
+/// blaming healthy peers.
+#[derive(Debug, Clone)]
+pub struct Awareness {
+    max_multiplier: u32,
+    score: u32,
+}
+
+impl Awareness {
+    pub fn new(max_multiplier: u32) -> Self {
+        Awareness {
+            max_multiplier,
+            score: 0,
+        }
+    }
+
+    pub fn apply_delta(&mut self, delta: i32) {
+        let upper = i64::from(self.max_multiplier.saturating_sub(1));
+        let score = (i64::from(self.score) + i64::from(delta)).max(0).min(upper);
+
+        self.score = u32::try_from(score).unwrap_or(0);
+    }
+
+    pub fn health_score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn scale_timeout(&self, timeout: Duration) -> Duration {
+        timeout * i32::try_from(self.score + 1).unwrap_or(i32::MAX)
+    }
+}
@@ -0,0 +1,164 @@
+use crate::epidemic::runtime;
+use chrono::{DateTime, Duration, Utc};
+use serde::*;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+///
+/// User message disseminated to every member along with the state changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArtilleryBroadcast {
+    pub id: Uuid,
+    pub origin: Uuid,
+    pub lamport: u64,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct QueuedBroadcast {
+    broadcast: ArtilleryBroadcast,
+    transmits: usize,
+}
+
+///
+/// Piggyback queue of the broadcasts, retired by the same retransmit limit as the
+/// state changes.
+#[derive(Debug, Default)]
+pub struct BroadcastQueue {
+    queue: Vec<QueuedBroadcast>,
+}
+
+impl BroadcastQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn enqueue(&mut self, broadcast: ArtilleryBroadcast) {
+        self.queue.push(QueuedBroadcast {
+            broadcast,
+            transmits: 0,
+        });
+    }
+
+    ///
+    /// Pending broadcasts, least transmitted and newest first.
+    pub fn prioritized(&self) -> Vec<ArtilleryBroadcast> {
+        let mut queue: Vec<_> = self.queue.iter().collect();
+        queue.sort_by(|l, r| {
+            l.transmits
+                .cmp(&r.transmits)
+                .then_with(|| r.broadcast.lamport.cmp(&l.broadcast.lamport))
+        });
+
+        queue.iter().map(|q| q.broadcast.clone()).collect()
+    }
+
+    pub fn mark_transmitted(&mut self, transmitted: &[ArtilleryBroadcast], limit: usize) {
+        let transmitted_ids: HashSet<_> = transmitted.iter().map(|b| b.id).collect();
+
+        for queued in &mut self.queue {
+            if transmitted_ids.contains(&queued.broadcast.id) {
+                queued.transmits += 1;
+            }
+        }
+
+        self.queue.retain(|q| q.transmits < limit);
+    }
+}
+
+///
+/// Lamport clock of the broadcasts along with the ids already delivered.
+///
+/// Delivered ids are remembered for the dedup window. Once an id is forgotten, the
+/// Lamport time of its origin is kept as a watermark, and anything from that origin
+/// at or below it is considered a stale duplicate.
+#[derive(Debug, Default)]
+pub struct BroadcastLog {
+    clock: u64,
+    seen: HashMap<Uuid, (Uuid, u64, DateTime<Utc>)>,
+    watermarks: HashMap<Uuid, u64>,
+}
+
+impl BroadcastLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Advances the clock for a broadcast originating here.
+    pub fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    ///
+    /// Records a broadcast. Returns `true` if it wasn't delivered before.
+    pub fn observe(&mut self, broadcast: &ArtilleryBroadcast, dedup_window: Duration) -> bool {
+        let now = runtime::now();
+        let watermarks = &mut self.watermarks;
+        self.seen.retain(|_, &mut (origin, lamport, seen_at)| {
+            let keep = seen_at + dedup_window >= now;
+            if !keep {
+                let watermark = watermarks.entry(origin).or_insert(0);
+                *watermark = lamport.max(*watermark);
+            }
+            keep
+        });
+
+        self.clock = self.clock.max(broadcast.lamport);
+
+        let stale = match self.watermarks.get(&broadcast.origin) {
+            Some(watermark) => broadcast.lamport <= *watermark,
+            None => false,
+        };
+        if stale || self.seen.contains_key(&broadcast.id) {
+            return false;
+        }
+
+        self.seen
+            .insert(broadcast.id, (broadcast.origin, broadcast.lamport, now));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ArtilleryBroadcast, BroadcastLog};
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    fn broadcast(origin: Uuid, lamport: u64) -> ArtilleryBroadcast {
+        ArtilleryBroadcast {
+            id: Uuid::new_v4(),
+            origin,
+            lamport,
+            topic: "config".into(),
+            payload: b"v2".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_broadcast_is_delivered_once() {
+        let origin = Uuid::new_v4();
+        let mut log = BroadcastLog::new();
+
+        let first = broadcast(origin, 5);
+        assert!(log.observe(&first, Duration::minutes(1)));
+        assert!(!log.observe(&first, Duration::minutes(1)));
+        assert_eq!(log.tick(), 6);
+
+        // Forgotten ids are still rejected by the watermark of their origin.
+        assert!(log.observe(&broadcast(origin, 7), Duration::milliseconds(-1)));
+        assert!(!log.observe(&first, Duration::milliseconds(-1)));
+        assert!(log.observe(&broadcast(Uuid::new_v4(), 1), Duration::minutes(1)));
+    }
+}
@@ -1,23 +1,40 @@
 use super::state::ArtilleryEpidemic;
-use crate::epidemic::cluster_config::ClusterConfig;
-use crate::epidemic::state::{ArtilleryClusterEvent, ArtilleryClusterRequest};
+use crate::epidemic::cluster_config::{ClusterConfig, ClusterConfigUpdate};
+use crate::epidemic::coordinate::Coordinate;
+use crate::epidemic::delegate::{EventDelegate, EventKind, SubscriptionConfig, SubscriptionId};
+use crate::epidemic::payload::{ArtilleryPayload, PayloadEvent};
+use crate::epidemic::query::{ArtilleryQuery, QueryFilter, QueryResponse};
+use crate::epidemic::state::{
+    ArtilleryClusterEvent, ArtilleryClusterRequest, DeliveryStatus, KeyringRequest, LeaveStatus,
+};
+use crate::epidemic::transport::{Transport, UdpTransport};
 use crate::errors::*;
+use crate::events::{bounded_event_channel, event_channel, EventReceiver};
+use crate::metrics::{Metrics, MetricsSnapshot};
 use bastion_executor::prelude::*;
+use futures::Stream;
 use lightproc::{proc_stack::ProcStack, recoverable_handle::RecoverableHandle};
+use mio::Waker;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::AsRef;
 use std::net::SocketAddr;
 use std::{
     future::Future,
     pin::Pin,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::mpsc::{channel, Sender},
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct Cluster {
-    pub events: Receiver<ArtilleryClusterEvent>,
+    pub events: EventReceiver<ArtilleryClusterEvent>,
     comm: Sender<ArtilleryClusterRequest>,
+    waker: Arc<Waker>,
+    metrics: Arc<Metrics>,
 }
 
 impl Cluster {
@@ -25,11 +42,31 @@ impl Cluster {
         host_key: Uuid,
         config: ClusterConfig,
     ) -> Result<(Self, RecoverableHandle<()>)> {
-        let (event_tx, event_rx) = channel::<ArtilleryClusterEvent>();
+        let transport = UdpTransport::bind(config.listen_addr)?;
+
+        Self::with_transport(host_key, config, Box::new(transport))
+    }
+
+    ///
+    /// Cluster gossiping over the given transport instead of a UDP socket.
+    pub fn with_transport(
+        host_key: Uuid,
+        config: ClusterConfig,
+        transport: Box<dyn Transport>,
+    ) -> Result<(Self, RecoverableHandle<()>)> {
+        let (event_tx, event_rx) =
+            bounded_event_channel::<ArtilleryClusterEvent>(config.events.capacity);
         let (internal_tx, mut internal_rx) = channel::<ArtilleryClusterRequest>();
 
-        let (poll, state) =
-            ArtilleryEpidemic::new(host_key, config, event_tx, internal_tx.clone())?;
+        let metrics = config.metrics.clone();
+        let (poll, state) = ArtilleryEpidemic::with_transport(
+            host_key,
+            config,
+            event_tx,
+            internal_tx.clone(),
+            transport,
+        )?;
+        let waker = state.request_waker();
 
         debug!("Starting Artillery Cluster");
         let cluster_handle = spawn_blocking(
@@ -44,6 +81,8 @@ impl Cluster {
             Self {
                 events: event_rx,
                 comm: internal_tx,
+                waker,
+                metrics,
             },
             cluster_handle,
         ))
@@ -53,27 +92,298 @@ impl Cluster {
         let _ = self.comm.send(ArtilleryClusterRequest::AddSeed(addr));
     }
 
-    pub fn send_payload<T: AsRef<str>>(&self, id: Uuid, msg: T) {
+    /// Adds seed nodes by a `name:port` host, which is resolved again periodically.
+    pub fn add_seed_host<T: AsRef<str>>(&self, host: T) {
+        let _ = self.comm.send(ArtilleryClusterRequest::AddSeedHost(
+            host.as_ref().to_string(),
+        ));
+    }
+
+    pub fn send_payload<T: AsRef<str>>(&self, id: Uuid, topic: T, data: Vec<u8>) {
         self.comm
             .send(ArtilleryClusterRequest::Payload(
                 id,
-                msg.as_ref().to_string(),
+                ArtilleryPayload::new(topic, data),
             ))
             .unwrap();
     }
 
+    /// Sends the value serialized with bincode, receivers get it back with
+    /// [`ArtilleryPayload::decode`].
+    pub fn send_typed<T: AsRef<str>, V: Serialize>(
+        &self,
+        id: Uuid,
+        topic: T,
+        value: &V,
+    ) -> Result<()> {
+        self.comm.send(ArtilleryClusterRequest::Payload(
+            id,
+            ArtilleryPayload::encode(topic, value)?,
+        ))?;
+
+        Ok(())
+    }
+
+    /// Subscribes to the payloads on the topic. They aren't emitted as cluster events
+    /// anymore while a subscriber is around; dropping the receiver unsubscribes.
+    pub fn subscribe<T: AsRef<str>>(&self, topic: T) -> EventReceiver<PayloadEvent> {
+        let (tx, rx) = event_channel();
+
+        let _ = self.comm.send(ArtilleryClusterRequest::Subscribe(
+            topic.as_ref().to_string(),
+            tx,
+        ));
+
+        rx
+    }
+
+    /// Subscribes to the cluster events alongside the `events` receiver. Dropping the
+    /// receiver unsubscribes as well.
+    pub fn subscribe_events(
+        &self,
+        config: SubscriptionConfig,
+    ) -> Result<(SubscriptionId, EventReceiver<ArtilleryClusterEvent>)> {
+        let (reply_tx, reply_rx) = channel();
+        let (tx, rx) = bounded_event_channel(config.capacity);
+
+        self.comm.send(ArtilleryClusterRequest::SubscribeEvents(
+            tx, config, reply_tx,
+        ))?;
+        self.waker.wake()?;
+
+        Ok((reply_rx.recv()?, rx))
+    }
+
+    /// Registers a delegate called with the events of the given kinds, every kind if
+    /// none are given.
+    pub fn register_delegate<D: EventDelegate + 'static>(
+        &self,
+        delegate: D,
+        kinds: Vec<EventKind>,
+    ) -> Result<SubscriptionId> {
+        let (tx, rx) = channel();
+
+        self.comm.send(ArtilleryClusterRequest::RegisterDelegate(
+            Arc::new(delegate),
+            kinds,
+            tx,
+        ))?;
+        self.waker.wake()?;
+
+        Ok(rx.recv()?)
+    }
+
+    /// Stops delivering events to the subscriber or delegate. Returns whether it was
+    /// still subscribed.
+    pub fn unsubscribe_events(&self, id: SubscriptionId) -> Result<bool> {
+        let (tx, rx) = channel();
+
+        self.comm
+            .send(ArtilleryClusterRequest::UnsubscribeEvents(id, tx))?;
+        self.waker.wake()?;
+
+        Ok(rx.recv()?)
+    }
+
     pub fn leave_cluster(&self) {
         let _ = self.comm.send(ArtilleryClusterRequest::LeaveCluster);
     }
+
+    /// Leaves the cluster and keeps gossiping the leave until enough members acked it,
+    /// or the leave timeout passed. Resolves to whichever happened first.
+    pub fn leave(&self) -> LeaveFuture {
+        let (tx, rx) = event_channel();
+
+        let _ = self.comm.send(ArtilleryClusterRequest::Leave(tx));
+        let _ = self.waker.wake();
+
+        Completion { status: rx }
+    }
+
+    /// Sends a payload to the member and retries it with a backoff until the member acks
+    /// it, or the payload timeout passes. Payloads too big for the network MTU are sent
+    /// over a TCP stream instead.
+    pub fn deliver_payload<T: AsRef<str>>(
+        &self,
+        id: Uuid,
+        topic: T,
+        data: Vec<u8>,
+    ) -> DeliveryFuture {
+        let (tx, rx) = event_channel();
+
+        let _ = self.comm.send(ArtilleryClusterRequest::DeliverPayload(
+            id,
+            ArtilleryPayload::new(topic, data),
+            tx,
+        ));
+
+        Completion { status: rx }
+    }
+
+    /// Exchanges the complete member list with the node at `addr` over TCP and merges
+    /// the answer into ours, blocking until the exchange is done.
+    pub fn sync_with(&self, addr: SocketAddr) -> Result<()> {
+        let (tx, rx) = channel();
+
+        self.comm
+            .send(ArtilleryClusterRequest::SyncWith(addr, Some(tx)))?;
+        self.waker.wake()?;
+
+        rx.recv()?
+    }
+
+    /// Replaces the metadata of this member and gossips it to the cluster.
+    /// Fails if the member wouldn't fit into a single packet anymore.
+    pub fn set_metadata(&self, metadata: BTreeMap<String, String>) -> Result<()> {
+        let (tx, rx) = channel();
+
+        self.comm
+            .send(ArtilleryClusterRequest::SetMetadata(metadata, tx))?;
+        self.waker.wake()?;
+
+        rx.recv()?
+    }
+
+    /// Changes the failure detection timings while the cluster keeps running.
+    /// Nothing is changed if the outcome would be invalid.
+    pub fn reconfigure(&self, update: ClusterConfigUpdate) -> Result<()> {
+        let (tx, rx) = channel();
+
+        self.comm
+            .send(ArtilleryClusterRequest::Reconfigure(update, tx))?;
+        self.waker.wake()?;
+
+        rx.recv()?
+    }
+
+    /// Disseminates a message to every member, this one included. It is piggybacked
+    /// on the gossip, so it has to fit into a single packet.
+    pub fn broadcast<T: AsRef<str>>(&self, topic: T, payload: Vec<u8>) -> Result<()> {
+        let (tx, rx) = channel();
+
+        self.comm.send(ArtilleryClusterRequest::Broadcast(
+            topic.as_ref().to_string(),
+            payload,
+            tx,
+        ))?;
+        self.waker.wake()?;
+
+        rx.recv()?
+    }
+
+    /// Asks every alive member matching the filter, this one included, and streams
+    /// their acks and responses. The stream ends once the timeout passes.
+    pub fn query<T: AsRef<str>>(
+        &self,
+        name: T,
+        payload: Vec<u8>,
+        filter: QueryFilter,
+        timeout: Duration,
+    ) -> Result<QueryResponses> {
+        let (tx, rx) = event_channel();
+
+        let query = ArtilleryQuery {
+            id: 0,
+            name: name.as_ref().to_string(),
+            payload,
+            filter,
+            timeout,
+        };
+        self.comm.send(ArtilleryClusterRequest::Query(query, tx))?;
+        // Queries are short lived, don't let them wait for the next probe.
+        self.waker.wake()?;
+
+        Ok(rx)
+    }
+
+    /// Network coordinates of the members, this one included, as far as they are known.
+    pub fn coordinates(&self) -> Result<HashMap<Uuid, Coordinate>> {
+        let (tx, rx) = channel();
+
+        self.comm.send(ArtilleryClusterRequest::Coordinates(tx))?;
+        self.waker.wake()?;
+
+        Ok(rx.recv()?)
+    }
+
+    /// Estimates the RTT between two members from their network coordinates.
+    /// Gives `None` while the coordinate of either one is unknown.
+    pub fn estimate_rtt(&self, from: &Uuid, to: &Uuid) -> Result<Option<Duration>> {
+        let coordinates = self.coordinates()?;
+
+        match (coordinates.get(from), coordinates.get(to)) {
+            (Some(source), Some(target)) if source.is_compatible_with(target) => {
+                Ok(Some(source.distance_to(target)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Current values of the metrics recorded by this member.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Installs a new gossip key, incoming gossip sealed with it is accepted from now on.
+    pub fn install_key<T: AsRef<[u8]>>(&self, key: T) -> Result<()> {
+        self.update_keyring(KeyringRequest::Install(key.as_ref().to_vec()))
+    }
+
+    /// Makes an already installed key the primary key used for outgoing gossip.
+    pub fn use_key<T: AsRef<[u8]>>(&self, key: T) -> Result<()> {
+        self.update_keyring(KeyringRequest::Use(key.as_ref().to_vec()))
+    }
+
+    /// Removes a secondary gossip key from the keyring.
+    pub fn remove_key<T: AsRef<[u8]>>(&self, key: T) -> Result<()> {
+        self.update_keyring(KeyringRequest::Remove(key.as_ref().to_vec()))
+    }
+
+    fn update_keyring(&self, request: KeyringRequest) -> Result<()> {
+        let (tx, rx) = channel();
+
+        self.comm
+            .send(ArtilleryClusterRequest::Keyring(request, tx))?;
+        self.waker.wake()?;
+
+        rx.recv()?
+    }
+}
+
+impl Stream for Cluster {
+    type Item = ArtilleryClusterEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.events.poll_event(cx)
+    }
 }
 
-impl Future for Cluster {
-    type Output = ArtilleryClusterEvent;
+///
+/// Outcome of an operation running on the cluster event loop.
+#[derive(Debug)]
+pub struct Completion<T> {
+    status: EventReceiver<T>,
+}
+
+/// Completion of a graceful leave, see [`Cluster::leave`].
+pub type LeaveFuture = Completion<LeaveStatus>;
+
+/// Completion of a payload delivery, see [`Cluster::deliver_payload`].
+pub type DeliveryFuture = Completion<DeliveryStatus>;
+
+/// Acks and responses to a query, see [`Cluster::query`].
+pub type QueryResponses = EventReceiver<QueryResponse>;
+
+impl<T> Future for Completion<T> {
+    type Output = Result<T>;
 
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
-        match self.events.recv() {
-            Ok(kv) => Poll::Ready(kv),
-            Err(_) => Poll::Pending,
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match self.status.poll_event(cx) {
+            Poll::Ready(Some(status)) => Poll::Ready(Ok(status)),
+            Poll::Ready(None) => Poll::Ready(Err(ArtilleryError::Receive(
+                "cluster stopped before the operation completed".into(),
+            ))),
+            Poll::Pending => Poll::Pending,
         }
     }
 }
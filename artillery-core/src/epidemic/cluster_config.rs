@@ -1,6 +1,15 @@
 use crate::constants::*;
+use crate::epidemic::coordinate::CoordinateConfig;
+use crate::epidemic::delegate::SubscriptionConfig;
+use crate::epidemic::quarantine::QuarantineConfig;
+use crate::epidemic::seeds::{SeedResolver, SystemResolver};
+use crate::errors::*;
+use crate::metrics::Metrics;
 use chrono::Duration;
+use std::collections::BTreeMap;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct ClusterConfig {
@@ -10,6 +19,64 @@ pub struct ClusterConfig {
     pub ping_request_host_count: usize,
     pub ping_timeout: Duration,
     pub listen_addr: SocketAddr,
+    /// Address other members reach us at, if it differs from where the packets come from
+    pub advertise_addr: Option<SocketAddr>,
+    /// Multiplier of the minimum suspicion timeout, scaled by `log10(cluster size)`
+    pub suspicion_mult: u32,
+    /// Upper bound of the suspicion timeout as a multiple of the minimum one
+    pub suspicion_max_timeout_mult: u32,
+    /// Upper bound of the local health multiplier applied to probe timings
+    pub awareness_max_multiplier: u32,
+    /// Wire protocol version to speak, lower it to stay compatible during rolling upgrades
+    pub protocol_version: u8,
+    /// Encrypts and authenticates the gossip with keys derived from `cluster_key`
+    pub gossip_encryption: bool,
+    /// Additional keys accepted for incoming gossip, used while rotating `cluster_key`
+    pub secondary_cluster_keys: Vec<Vec<u8>>,
+    /// Multiplier of the state change retransmissions, scaled by `log10(cluster size + 1)`
+    pub retransmit_mult: usize,
+    /// Interval of the full state push/pull with a random member, zero disables it
+    pub push_pull_interval: Duration,
+    /// Connect, read and write timeout of the TCP streams
+    pub stream_timeout: Duration,
+    /// Incoming TCP streams served at once, further connections are refused
+    pub max_incoming_streams: usize,
+    /// Number of members which have to ack our `Left` state for a confirmed leave
+    pub leave_confirmations: usize,
+    /// Time to wait for the leave confirmations
+    pub leave_timeout: Duration,
+    /// Time a `Down` member is kept in the member list before it is reaped
+    pub down_reap_timeout: Duration,
+    /// Time a `Left` member is kept in the member list before it is reaped
+    pub left_reap_timeout: Duration,
+    /// Time gossip about a reaped member is ignored, unless it has a newer incarnation
+    pub tombstone_timeout: Duration,
+    /// Time a delivered payload is retried until the target acks it
+    pub payload_timeout: Duration,
+    /// Delay before the first payload retry, doubled on every further retry
+    pub payload_retry_interval: Duration,
+    /// Time the ids of delivered broadcasts are remembered to drop duplicates
+    pub broadcast_dedup_window: Duration,
+    /// Tuning of the Vivaldi network coordinates
+    pub coordinates: CoordinateConfig,
+    /// Interval of resolving the seed hosts again, zero disables it
+    pub seed_resolve_interval: Duration,
+    /// Resolver of the seed hosts
+    pub seed_resolver: Arc<dyn SeedResolver>,
+    /// File the member list is snapshotted to, its peers seed the next start
+    pub snapshot_path: Option<PathBuf>,
+    /// Interval of writing the snapshot
+    pub snapshot_interval: Duration,
+    /// Quarantine of the sources repeatedly sending undecodable packets or wrong cluster keys
+    pub quarantine: QuarantineConfig,
+    /// Queue of the `events` receiver of the cluster
+    pub events: SubscriptionConfig,
+    /// Registry the event loop records its metrics into
+    pub metrics: Arc<Metrics>,
+    /// Address the metrics are served at for Prometheus, unset disables it
+    pub metrics_addr: Option<SocketAddr>,
+    /// Initial metadata of this member, gossiped along with its state
+    pub metadata: BTreeMap<String, String>,
 }
 
 impl Default for ClusterConfig {
@@ -23,6 +90,164 @@ impl Default for ClusterConfig {
             ping_request_host_count: 3,
             ping_timeout: Duration::seconds(3),
             listen_addr: directed.to_socket_addrs().unwrap().next().unwrap(),
+            advertise_addr: None,
+            suspicion_mult: 4,
+            suspicion_max_timeout_mult: 6,
+            awareness_max_multiplier: 8,
+            protocol_version: CONST_PROTOCOL_VERSION,
+            gossip_encryption: false,
+            secondary_cluster_keys: Vec::new(),
+            retransmit_mult: 4,
+            push_pull_interval: Duration::seconds(30),
+            stream_timeout: Duration::seconds(10),
+            max_incoming_streams: 32,
+            leave_confirmations: 3,
+            leave_timeout: Duration::seconds(5),
+            down_reap_timeout: Duration::hours(1),
+            left_reap_timeout: Duration::minutes(5),
+            tombstone_timeout: Duration::minutes(5),
+            payload_timeout: Duration::seconds(5),
+            payload_retry_interval: Duration::milliseconds(200),
+            broadcast_dedup_window: Duration::minutes(1),
+            coordinates: CoordinateConfig::default(),
+            seed_resolve_interval: Duration::seconds(30),
+            seed_resolver: Arc::new(SystemResolver),
+            snapshot_path: None,
+            snapshot_interval: Duration::seconds(30),
+            quarantine: QuarantineConfig::default(),
+            events: SubscriptionConfig::default(),
+            metrics: Arc::new(Metrics::default()),
+            metrics_addr: None,
+            metadata: BTreeMap::new(),
         }
     }
 }
+
+///
+/// Changes to the failure detection of a running cluster, unset fields are kept.
+#[derive(Debug, Clone, Default)]
+pub struct ClusterConfigUpdate {
+    pub ping_interval: Option<Duration>,
+    pub ping_timeout: Option<Duration>,
+    pub ping_request_host_count: Option<usize>,
+    pub network_mtu: Option<usize>,
+    pub suspicion_mult: Option<u32>,
+    pub suspicion_max_timeout_mult: Option<u32>,
+    pub retransmit_mult: Option<usize>,
+    /// Zero disables the periodic push/pull
+    pub push_pull_interval: Option<Duration>,
+}
+
+impl ClusterConfig {
+    ///
+    /// Copy of this config with the update applied, provided the outcome is usable.
+    pub fn updated(&self, update: &ClusterConfigUpdate) -> Result<ClusterConfig> {
+        let mut config = self.clone();
+
+        if let Some(ping_interval) = update.ping_interval {
+            config.ping_interval = ping_interval;
+        }
+        if let Some(ping_timeout) = update.ping_timeout {
+            config.ping_timeout = ping_timeout;
+        }
+        if let Some(host_count) = update.ping_request_host_count {
+            config.ping_request_host_count = host_count;
+        }
+        if let Some(network_mtu) = update.network_mtu {
+            config.network_mtu = network_mtu;
+        }
+        if let Some(suspicion_mult) = update.suspicion_mult {
+            config.suspicion_mult = suspicion_mult;
+        }
+        if let Some(max_timeout_mult) = update.suspicion_max_timeout_mult {
+            config.suspicion_max_timeout_mult = max_timeout_mult;
+        }
+        if let Some(retransmit_mult) = update.retransmit_mult {
+            config.retransmit_mult = retransmit_mult;
+        }
+        if let Some(push_pull_interval) = update.push_pull_interval {
+            config.push_pull_interval = push_pull_interval;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    ///
+    /// Fails if the failure detection settings aren't usable.
+    pub fn validate(&self) -> Result<()> {
+        if self.ping_interval <= Duration::zero() || self.ping_timeout <= Duration::zero() {
+            bail!(
+                ArtilleryError::Config,
+                "ping interval and timeout must be positive"
+            );
+        }
+        if self.network_mtu == 0 || self.network_mtu > CONST_PACKET_SIZE {
+            bail!(
+                ArtilleryError::Config,
+                "network MTU must be between 1 and {} bytes",
+                CONST_PACKET_SIZE
+            );
+        }
+        if self.suspicion_mult == 0
+            || self.suspicion_max_timeout_mult == 0
+            || self.retransmit_mult == 0
+        {
+            bail!(ArtilleryError::Config, "multipliers must be at least 1");
+        }
+        if self.push_pull_interval < Duration::zero() {
+            bail!(
+                ArtilleryError::Config,
+                "push/pull interval can't be negative"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClusterConfig, ClusterConfigUpdate};
+    use chrono::Duration;
+
+    #[test]
+    fn test_update_keeps_unset_fields() {
+        let config = ClusterConfig::default();
+
+        let tightened = config
+            .updated(&ClusterConfigUpdate {
+                ping_interval: Some(Duration::milliseconds(200)),
+                ping_request_host_count: Some(5),
+                ..ClusterConfigUpdate::default()
+            })
+            .unwrap();
+        assert_eq!(tightened.ping_interval, Duration::milliseconds(200));
+        assert_eq!(tightened.ping_request_host_count, 5);
+        assert_eq!(tightened.ping_timeout, config.ping_timeout);
+
+        let invalid = ClusterConfigUpdate {
+            network_mtu: Some(0),
+            ..ClusterConfigUpdate::default()
+        };
+        assert!(config.updated(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_unusable_settings() {
+        assert!(ClusterConfig::default().validate().is_ok());
+
+        let no_retransmits = ClusterConfig {
+            retransmit_mult: 0,
+            ..ClusterConfig::default()
+        };
+        assert!(no_retransmits.validate().is_err());
+
+        let no_timeout = ClusterConfig {
+            ping_timeout: Duration::zero(),
+            ..ClusterConfig::default()
+        };
+        assert!(no_timeout.validate().is_err());
+    }
+}
@@ -0,0 +1,278 @@
+// Vivaldi is all about floating point geometry.
+#![allow(
+    clippy::float_arithmetic,
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+
+use crate::epidemic::runtime;
+use serde::*;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use uuid::Uuid;
+
+// Distances below this are treated as zero, to steer clear of divisions by zero.
+const ZERO_THRESHOLD: f64 = 1.0e-6;
+
+#[derive(Debug, Clone)]
+pub struct CoordinateConfig {
+    /// Dimensions of the Euclidean part of the coordinates
+    pub dimensionality: usize,
+    /// Upper bound of the error estimate, also the error of a fresh coordinate
+    pub vivaldi_error_max: f64,
+    /// Weight of a new sample in the error estimate
+    pub vivaldi_ce: f64,
+    /// Step size of the coordinate updates
+    pub vivaldi_cc: f64,
+    /// Number of samples averaged into the adjustment term, zero disables it
+    pub adjustment_window_size: usize,
+    /// Minimum height, in seconds, of a coordinate
+    pub height_min: f64,
+    /// Number of recent RTT samples per member whose median is used
+    pub latency_filter_size: usize,
+    /// Distance from the origin, in seconds, at which gravity pulls coordinates back
+    pub gravity_rho: f64,
+}
+
+impl Default for CoordinateConfig {
+    fn default() -> Self {
+        CoordinateConfig {
+            dimensionality: 8,
+            vivaldi_error_max: 1.5,
+            vivaldi_ce: 0.25,
+            vivaldi_cc: 0.25,
+            adjustment_window_size: 20,
+            height_min: 10.0e-6,
+            latency_filter_size: 3,
+            gravity_rho: 150.0,
+        }
+    }
+}
+
+///
+/// Vivaldi network coordinate, distances between coordinates estimate the RTT in seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Coordinate {
+    pub vec: Vec<f64>,
+    pub error: f64,
+    pub adjustment: f64,
+    pub height: f64,
+}
+
+impl Coordinate {
+    pub fn new(config: &CoordinateConfig) -> Self {
+        Coordinate {
+            vec: vec![0.0; config.dimensionality],
+            error: config.vivaldi_error_max,
+            adjustment: 0.0,
+            height: config.height_min,
+        }
+    }
+
+    ///
+    /// Whether both coordinates have the same dimensions, others can't be compared.
+    pub fn is_compatible_with(&self, other: &Coordinate) -> bool {
+        self.vec.len() == other.vec.len()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.vec
+            .iter()
+            .chain(&[self.error, self.adjustment, self.height])
+            .all(|c| c.is_finite())
+    }
+
+    ///
+    /// Estimated RTT to the other coordinate.
+    pub fn distance_to(&self, other: &Coordinate) -> Duration {
+        let raw = self.raw_distance_to(other);
+        let adjusted = raw + self.adjustment + other.adjustment;
+        let distance = if adjusted > 0.0 { adjusted } else { raw };
+
+        // Bounded, as conversion panics on overflow.
+        Duration::from_secs_f64(distance.max(0.0).min(f64::from(u32::MAX)))
+    }
+
+    fn raw_distance_to(&self, other: &Coordinate) -> f64 {
+        magnitude(&difference(&self.vec, &other.vec)) + self.height + other.height
+    }
+
+    fn apply_force(&mut self, config: &CoordinateConfig, force: f64, other: &Coordinate) {
+        let (unit, distance) = unit_vector_at(&self.vec, &other.vec);
+
+        for (c, u) in self.vec.iter_mut().zip(&unit) {
+            *c += u * force;
+        }
+
+        if distance > ZERO_THRESHOLD {
+            self.height = (self.height + other.height) * force / distance + self.height;
+            self.height = self.height.max(config.height_min);
+        }
+    }
+}
+
+///
+/// Maintains the coordinate of this node from the RTTs measured to other members.
+#[derive(Debug)]
+pub struct CoordinateClient {
+    config: CoordinateConfig,
+    coordinate: Coordinate,
+    origin: Coordinate,
+    adjustment_samples: VecDeque<f64>,
+    latency_samples: HashMap<Uuid, VecDeque<f64>>,
+}
+
+impl CoordinateClient {
+    pub fn new(config: CoordinateConfig) -> Self {
+        let coordinate = Coordinate::new(&config);
+
+        CoordinateClient {
+            origin: coordinate.clone(),
+            coordinate,
+            adjustment_samples: VecDeque::new(),
+            latency_samples: HashMap::new(),
+            config,
+        }
+    }
+
+    pub fn coordinate(&self) -> &Coordinate {
+        &self.coordinate
+    }
+
+    pub fn forget(&mut self, member: &Uuid) {
+        self.latency_samples.remove(member);
+    }
+
+    ///
+    /// Moves our coordinate according to an RTT measured to the member at `other`.
+    /// Incompatible or invalid coordinates are ignored.
+    pub fn update(&mut self, member: Uuid, other: &Coordinate, rtt: Duration) -> bool {
+        if !self.coordinate.is_compatible_with(other) || !other.is_valid() {
+            return false;
+        }
+
+        let latency = self.filtered_latency(member, rtt.as_secs_f64());
+        let previous = self.coordinate.clone();
+
+        self.update_vivaldi(other, latency);
+        self.update_adjustment(other, latency);
+        self.update_gravity();
+
+        // Numeric trouble must not poison the coordinate.
+        if !self.coordinate.is_valid() {
+            self.coordinate = previous;
+            return false;
+        }
+
+        true
+    }
+
+    fn filtered_latency(&mut self, member: Uuid, rtt: f64) -> f64 {
+        let window = self.config.latency_filter_size.max(1);
+        let samples = self.latency_samples.entry(member).or_default();
+
+        samples.push_back(rtt);
+        while samples.len() > window {
+            samples.pop_front();
+        }
+
+        let mut sorted: Vec<f64> = samples.iter().cloned().collect();
+        sorted.sort_by(|l, r| l.partial_cmp(r).unwrap_or(std::cmp::Ordering::Equal));
+
+        sorted.get(sorted.len() / 2).cloned().unwrap_or(rtt)
+    }
+
+    fn update_vivaldi(&mut self, other: &Coordinate, latency: f64) {
+        let rtt = latency.max(ZERO_THRESHOLD);
+        let distance = self.coordinate.raw_distance_to(other).max(ZERO_THRESHOLD);
+
+        let wrongness = (distance - rtt).abs() / rtt;
+        let total_error = (self.coordinate.error + other.error).max(ZERO_THRESHOLD);
+        let weight = self.coordinate.error / total_error;
+
+        let ce = self.config.vivaldi_ce * weight;
+        self.coordinate.error = (ce * wrongness + self.coordinate.error * (1.0 - ce))
+            .min(self.config.vivaldi_error_max);
+
+        let force = self.config.vivaldi_cc * weight * (rtt - distance);
+        self.coordinate.apply_force(&self.config, force, other);
+    }
+
+    fn update_adjustment(&mut self, other: &Coordinate, rtt: f64) {
+        let window = self.config.adjustment_window_size;
+        if window == 0 {
+            return;
+        }
+
+        self.adjustment_samples
+            .push_back(rtt - self.coordinate.raw_distance_to(other));
+        while self.adjustment_samples.len() > window {
+            self.adjustment_samples.pop_front();
+        }
+
+        let sum: f64 = self.adjustment_samples.iter().sum();
+        self.coordinate.adjustment = sum / (2.0 * window as f64);
+    }
+
+    fn update_gravity(&mut self) {
+        let distance = self.coordinate.raw_distance_to(&self.origin);
+        let force = -(distance / self.config.gravity_rho).powi(2);
+
+        let origin = self.origin.clone();
+        self.coordinate.apply_force(&self.config, force, &origin);
+    }
+}
+
+fn difference(l: &[f64], r: &[f64]) -> Vec<f64> {
+    l.iter().zip(r).map(|(a, b)| a - b).collect()
+}
+
+fn magnitude(v: &[f64]) -> f64 {
+    v.iter().map(|c| c * c).sum::<f64>().sqrt()
+}
+
+///
+/// Unit vector pointing from `r` to `l` along with their distance. Coinciding points
+/// are pushed apart in a random direction.
+fn unit_vector_at(l: &[f64], r: &[f64]) -> (Vec<f64>, f64) {
+    let diff = difference(l, r);
+    let distance = magnitude(&diff);
+    if distance > ZERO_THRESHOLD {
+        return (diff.iter().map(|c| c / distance).collect(), distance);
+    }
+
+    let random: Vec<f64> = l.iter().map(|_| runtime::random_f64() - 0.5).collect();
+    let random_magnitude = magnitude(&random);
+    if random_magnitude > ZERO_THRESHOLD {
+        return (random.iter().map(|c| c / random_magnitude).collect(), 0.0);
+    }
+
+    (vec![0.0; l.len()], 0.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CoordinateClient, CoordinateConfig};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_coordinates_converge_to_rtt() {
+        let config = CoordinateConfig::default();
+        let mut a = CoordinateClient::new(config.clone());
+        let mut b = CoordinateClient::new(config);
+        let (a_key, b_key) = (Uuid::new_v4(), Uuid::new_v4());
+        let rtt = Duration::from_millis(50);
+
+        for _ in 0..200 {
+            let b_coordinate = b.coordinate().clone();
+            assert!(a.update(b_key, &b_coordinate, rtt));
+            let a_coordinate = a.coordinate().clone();
+            assert!(b.update(a_key, &a_coordinate, rtt));
+        }
+
+        let estimate = a.coordinate().distance_to(b.coordinate()).as_secs_f64();
+        assert!((estimate - 0.05).abs() < 0.005, "estimated {}", estimate);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::epidemic::member::ArtilleryMember;
+use crate::epidemic::state::{ArtilleryClusterEvent, ArtilleryMemberEvent};
+use crate::events::EventSender;
+use crate::metrics::Metrics;
+use crossbeam_channel::TrySendError;
+use std::fmt;
+use std::sync::Arc;
+
+///
+/// Receives the cluster events on the event loop, so it has to return quickly.
+pub trait EventDelegate: Send + Sync {
+    fn notify(&self, members: &[ArtilleryMember], event: &ArtilleryMemberEvent);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Joined,
+    WentUp,
+    SuspectedDown,
+    WentDown,
+    Left,
+    Updated,
+    Reaped,
+    AddressChanged,
+    Conflict,
+    Payload,
+    Broadcast,
+    Query,
+}
+
+impl ArtilleryMemberEvent {
+    pub fn kind(&self) -> EventKind {
+        use ArtilleryMemberEvent::*;
+
+        match self {
+            Joined(_) => EventKind::Joined,
+            WentUp(_) => EventKind::WentUp,
+            SuspectedDown(_) => EventKind::SuspectedDown,
+            WentDown(_) => EventKind::WentDown,
+            Left(_) => EventKind::Left,
+            Updated(_) => EventKind::Updated,
+            Reaped(_) => EventKind::Reaped,
+            AddressChanged(..) => EventKind::AddressChanged,
+            Conflict(..) => EventKind::Conflict,
+            Payload(..) => EventKind::Payload,
+            Broadcast(_) => EventKind::Broadcast,
+            Query(_) => EventKind::Query,
+        }
+    }
+}
+
+///
+/// What happens to an event for a subscriber whose queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// New event is dropped
+    DropNewest,
+    /// Oldest queued event is dropped to make room
+    DropOldest,
+    /// Subscriber is unsubscribed, its receiver ends once drained
+    Unsubscribe,
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionConfig {
+    /// Events queued for the subscriber at most
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// Kinds of events delivered, empty delivers every kind
+    pub kinds: Vec<EventKind>,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        SubscriptionConfig {
+            capacity: 1024,
+            overflow: OverflowPolicy::DropOldest,
+            kinds: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+enum Sink {
+    Queue(EventSender<ArtilleryClusterEvent>, OverflowPolicy),
+    Delegate(Arc<dyn EventDelegate>),
+}
+
+struct Subscriber {
+    id: SubscriptionId,
+    kinds: Vec<EventKind>,
+    sink: Sink,
+}
+
+impl Subscriber {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.kinds.is_empty() || self.kinds.contains(&kind)
+    }
+
+    ///
+    /// Hands the event over, returns whether the subscriber is still around.
+    fn deliver(&self, event: &ArtilleryClusterEvent, metrics: &Metrics) -> bool {
+        let (tx, overflow) = match &self.sink {
+            Sink::Delegate(delegate) => {
+                delegate.notify(&event.0, &event.1);
+                return true;
+            }
+            Sink::Queue(tx, overflow) => (tx, *overflow),
+        };
+
+        match tx.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+            Err(TrySendError::Full(rejected)) => {
+                metrics.dropped_events.inc();
+
+                match overflow {
+                    OverflowPolicy::DropNewest => true,
+                    OverflowPolicy::DropOldest => {
+                        let _ = tx.evict_oldest();
+                        // Consumer might have made room in the meantime as well.
+                        let _ = tx.try_send(rejected);
+                        true
+                    }
+                    OverflowPolicy::Unsubscribe => {
+                        warn!("Unsubscribing {:?}, its event queue is full", self.id);
+                        false
+                    }
+                }
+            }
+        }
+    }
+}
+
+///
+/// Fans the cluster events out to the subscribers and delegates.
+#[derive(Default)]
+pub struct EventDispatcher {
+    next_id: u64,
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventDispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(
+        &mut self,
+        tx: EventSender<ArtilleryClusterEvent>,
+        config: &SubscriptionConfig,
+    ) -> SubscriptionId {
+        self.add(config.kinds.clone(), Sink::Queue(tx, config.overflow))
+    }
+
+    pub fn register(
+        &mut self,
+        delegate: Arc<dyn EventDelegate>,
+        kinds: Vec<EventKind>,
+    ) -> SubscriptionId {
+        self.add(kinds, Sink::Delegate(delegate))
+    }
+
+    fn add(&mut self, kinds: Vec<EventKind>, sink: Sink) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push(Subscriber { id, kinds, sink });
+
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|subscriber| subscriber.id != id);
+
+        self.subscribers.len() != before
+    }
+
+    pub fn dispatch(&mut self, event: &ArtilleryClusterEvent, metrics: &Metrics) {
+        let kind = event.1.kind();
+
+        self.subscribers
+            .retain(|subscriber| !subscriber.wants(kind) || subscriber.deliver(event, metrics));
+    }
+}
+
+impl fmt::Debug for EventDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventDispatcher")
+            .field("next_id", &self.next_id)
+            .field("subscribers", &self.subscribers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventDispatcher, EventKind, OverflowPolicy, SubscriptionConfig};
+    use crate::epidemic::member::ArtilleryMember;
+    use crate::epidemic::state::ArtilleryMemberEvent;
+    use crate::events::bounded_event_channel;
+    use crate::metrics::Metrics;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_subscribers_get_their_kinds_within_capacity() {
+        let metrics = Metrics::default();
+        let mut dispatcher = EventDispatcher::new();
+        let member = ArtilleryMember::current(Uuid::new_v4());
+
+        let (all_tx, all_rx) = bounded_event_channel(2);
+        dispatcher.subscribe(all_tx, &SubscriptionConfig::default());
+        let (joins_tx, joins_rx) = bounded_event_channel(8);
+        let joins = SubscriptionConfig {
+            kinds: vec![EventKind::Joined],
+            overflow: OverflowPolicy::Unsubscribe,
+            ..SubscriptionConfig::default()
+        };
+        let joins_id = dispatcher.subscribe(joins_tx, &joins);
+
+        for event in vec![
+            ArtilleryMemberEvent::Joined(member.clone()),
+            ArtilleryMemberEvent::Updated(member.clone()),
+            ArtilleryMemberEvent::Reaped(member.clone()),
+        ] {
+            dispatcher.dispatch(&(Vec::new(), event), &metrics);
+        }
+
+        let kinds: Vec<_> = all_rx.try_iter().map(|(_, event)| event.kind()).collect();
+        assert_eq!(kinds, vec![EventKind::Updated, EventKind::Reaped]);
+        assert_eq!(metrics.dropped_events.get(), 1);
+        assert_eq!(joins_rx.try_iter().count(), 1);
+
+        drop(all_rx);
+        dispatcher.dispatch(&(Vec::new(), ArtilleryMemberEvent::Left(member)), &metrics);
+        assert!(!dispatcher.unsubscribe(super::SubscriptionId(0)));
+        assert!(dispatcher.unsubscribe(joins_id));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::epidemic::member::{ArtilleryMember, ArtilleryStateChange};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone)]
+struct QueuedStateChange {
+    state_change: ArtilleryStateChange,
+    transmits: usize,
+    sequence: u64,
+}
+
+///
+/// Piggyback queue of the state changes waiting for dissemination.
+///
+/// As in SWIM, every state change is retransmitted a bounded number of times, see
+/// [`retransmit_limit`]. Packets are filled with the least transmitted changes
+/// first and, among those, with the newest ones.
+#[derive(Debug, Default)]
+pub struct StateChangeQueue {
+    queue: Vec<QueuedStateChange>,
+    sequence: u64,
+}
+
+impl StateChangeQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    ///
+    /// Queues the latest state of a member. A pending change of the same member is
+    /// superseded, and the new change starts over with zero transmissions.
+    pub fn enqueue(&mut self, member: ArtilleryMember) {
+        self.enqueue_change(ArtilleryStateChange::new(member));
+    }
+
+    pub fn enqueue_change(&mut self, state_change: ArtilleryStateChange) {
+        let host_key = state_change.member().host_key();
+        self.sequence += 1;
+        self.queue
+            .retain(|q| q.state_change.member().host_key() != host_key);
+
+        self.queue.push(QueuedStateChange {
+            state_change,
+            transmits: 0,
+            sequence: self.sequence,
+        });
+    }
+
+    pub fn enqueue_all(&mut self, members: &[ArtilleryMember]) {
+        for member in members {
+            self.enqueue(member.clone());
+        }
+    }
+
+    ///
+    /// Pending state changes in the order they should be piggybacked.
+    pub fn prioritized(&self) -> Vec<ArtilleryStateChange> {
+        let mut queue: Vec<_> = self.queue.iter().collect();
+        queue.sort_by(|l, r| {
+            l.transmits
+                .cmp(&r.transmits)
+                .then_with(|| r.sequence.cmp(&l.sequence))
+        });
+
+        queue.iter().map(|q| q.state_change.clone()).collect()
+    }
+
+    ///
+    /// Counts a transmission of the given state changes and retires the ones
+    /// which reached the retransmit limit.
+    pub fn mark_transmitted(&mut self, transmitted: &[ArtilleryStateChange], limit: usize) {
+        let transmitted_keys: HashSet<_> = transmitted
+            .iter()
+            .map(|sc| sc.member().host_key())
+            .collect();
+
+        for queued in &mut self.queue {
+            if transmitted_keys.contains(&queued.state_change.member().host_key()) {
+                queued.transmits += 1;
+            }
+        }
+
+        self.queue.retain(|q| q.transmits < limit);
+    }
+}
+
+///
+/// Number of times a state change is retransmitted, `mult * ceil(log10(n + 1))`.
+pub fn retransmit_limit(retransmit_mult: usize, member_count: usize) -> usize {
+    let mut node_scale = 0;
+    let mut power = 1_usize;
+
+    while power < member_count.saturating_add(1) {
+        power = power.saturating_mul(10);
+        node_scale += 1;
+    }
+
+    retransmit_mult.saturating_mul(node_scale.max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{retransmit_limit, StateChangeQueue};
+    use crate::epidemic::member::{ArtilleryMember, ArtilleryMemberState};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    fn member(port: u16) -> ArtilleryMember {
+        ArtilleryMember::new(
+            Uuid::new_v4(),
+            FromStr::from_str(&format!("127.0.0.1:{}", port)).unwrap(),
+            0,
+            ArtilleryMemberState::Alive,
+        )
+    }
+
+    #[test]
+    fn test_retransmit_limit() {
+        assert_eq!(retransmit_limit(4, 0), 4);
+        assert_eq!(retransmit_limit(4, 9), 4);
+        assert_eq!(retransmit_limit(4, 60), 8);
+        assert_eq!(retransmit_limit(4, 1000), 16);
+    }
+
+    #[test]
+    fn test_least_transmitted_and_newest_first() {
+        let mut queue = StateChangeQueue::new();
+        let (first, second, third) = (member(1), member(2), member(3));
+
+        queue.enqueue_all(&[first.clone(), second.clone()]);
+        let transmitted = queue.prioritized();
+        assert_eq!(transmitted[0].member(), &second);
+
+        queue.mark_transmitted(&transmitted[..1], 2);
+        queue.enqueue(third.clone());
+
+        let order: Vec<_> = queue
+            .prioritized()
+            .iter()
+            .map(|sc| sc.member().clone())
+            .collect();
+        assert_eq!(order, vec![third, first, second.clone()]);
+
+        queue.mark_transmitted(&queue.prioritized(), 2);
+        assert_eq!(queue.len(), 2);
+        assert!(queue.prioritized().iter().all(|sc| sc.member() != &second));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::errors::*;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Bytes added to every packet by the encryption
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+
+type GossipKey = [u8; KEY_LEN];
+
+///
+/// Keyring used to authenticate and encrypt the gossip.
+///
+/// Outgoing packets are always sealed with the primary key, incoming packets are
+/// accepted if any of the installed keys opens them. Rotating the keys of a live
+/// cluster is done by installing the new key everywhere, switching the primary key
+/// over to it and finally removing the old key.
+#[derive(Clone)]
+pub struct Keyring {
+    // First key is the primary key.
+    keys: Vec<GossipKey>,
+}
+
+impl Keyring {
+    pub fn new(primary: &[u8], secondaries: &[Vec<u8>]) -> Self {
+        let mut keyring = Keyring {
+            keys: vec![derive_key(primary)],
+        };
+
+        for secondary in secondaries {
+            keyring.install_key(secondary);
+        }
+
+        keyring
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn install_key(&mut self, secret: &[u8]) {
+        let key = derive_key(secret);
+
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+    }
+
+    pub fn use_key(&mut self, secret: &[u8]) -> Result<()> {
+        let key = derive_key(secret);
+
+        match self.keys.iter().position(|k| *k == key) {
+            Some(idx) => {
+                self.keys.swap(0, idx);
+                Ok(())
+            }
+            None => Err(ArtilleryError::Keyring(
+                "key is not installed in the keyring".into(),
+            )),
+        }
+    }
+
+    pub fn remove_key(&mut self, secret: &[u8]) -> Result<()> {
+        let key = derive_key(secret);
+
+        match self.keys.iter().position(|k| *k == key) {
+            Some(0) => Err(ArtilleryError::Keyring(
+                "primary key can't be removed".into(),
+            )),
+            Some(idx) => {
+                self.keys.remove(idx);
+                Ok(())
+            }
+            None => Err(ArtilleryError::Keyring(
+                "key is not installed in the keyring".into(),
+            )),
+        }
+    }
+
+    ///
+    /// Seals the plaintext with the primary key. Header is authenticated but not encrypted.
+    pub fn encrypt(&self, header: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0_u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let cipher = Aes256Gcm::new(&self.keys[0].into());
+        let ciphertext = cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: plaintext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| ArtilleryError::Keyring("encryption failed".into()))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    ///
+    /// Opens a sealed payload with any of the installed keys.
+    pub fn decrypt(&self, header: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < ENCRYPTION_OVERHEAD {
+            bail!(ArtilleryError::Keyring, "encrypted payload is too short");
+        }
+
+        let (nonce_bytes, ciphertext) = sealed.split_at(NONCE_LEN);
+        let mut nonce = [0_u8; NONCE_LEN];
+        nonce.copy_from_slice(nonce_bytes);
+
+        for key in &self.keys {
+            let cipher = Aes256Gcm::new(&(*key).into());
+            let opened = cipher.decrypt(
+                &nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            );
+
+            if let Ok(plaintext) = opened {
+                return Ok(plaintext);
+            }
+        }
+
+        Err(ArtilleryError::Keyring(
+            "no installed key could decrypt the payload".into(),
+        ))
+    }
+}
+
+fn derive_key(secret: &[u8]) -> GossipKey {
+    let digest = Sha256::new()
+        .chain(b"artillery-gossip-key")
+        .chain(secret)
+        .finalize();
+
+    let mut key = [0_u8; KEY_LEN];
+    key.copy_from_slice(&digest);
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use super::Keyring;
+
+    #[test]
+    fn test_key_rotation() {
+        let old = Keyring::new(b"old", &[]);
+        let mut rotating = Keyring::new(b"old", &[]);
+
+        let sealed = old.encrypt(b"h", b"gossip").unwrap();
+        assert!(Keyring::new(b"other", &[]).decrypt(b"h", &sealed).is_err());
+        assert!(old.decrypt(b"x", &sealed).is_err());
+
+        rotating.install_key(b"new");
+        rotating.use_key(b"new").unwrap();
+        assert!(rotating.remove_key(b"new").is_err());
+
+        // Old members can still talk to us until the old key is gone.
+        assert_eq!(rotating.decrypt(b"h", &sealed).unwrap(), b"gossip");
+        rotating.remove_key(b"old").unwrap();
+        assert!(rotating.decrypt(b"h", &sealed).is_err());
+
+        let sealed = rotating.encrypt(b"h", b"gossip").unwrap();
+        assert!(old.decrypt(b"h", &sealed).is_err());
+        assert_eq!(
+            Keyring::new(b"new", &[]).decrypt(b"h", &sealed).unwrap(),
+            b"gossip"
+        );
+    }
+}
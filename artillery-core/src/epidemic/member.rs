@@ -1,8 +1,10 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::net::SocketAddr;
 
+use crate::epidemic::runtime;
 use chrono::{DateTime, Duration, Utc};
 use serde::*;
 use uuid::Uuid;
@@ -35,11 +37,15 @@ pub struct ArtilleryMember {
     member_state: ArtilleryMemberState,
     #[serde(rename = "t")]
     last_state_change: DateTime<Utc>,
+    #[serde(rename = "d")]
+    metadata: BTreeMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub struct ArtilleryStateChange {
     member: ArtilleryMember,
+    /// Member that started the suspicion, carried along with `Suspect` changes
+    suspector: Option<Uuid>,
 }
 
 impl ArtilleryMember {
@@ -54,7 +60,8 @@ impl ArtilleryMember {
             remote_host: Some(remote_host),
             incarnation_number,
             member_state: known_state,
-            last_state_change: Utc::now(),
+            last_state_change: runtime::now(),
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -64,7 +71,8 @@ impl ArtilleryMember {
             remote_host: None,
             incarnation_number: 0,
             member_state: ArtilleryMemberState::Alive,
-            last_state_change: Utc::now(),
+            last_state_change: runtime::now(),
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -76,6 +84,18 @@ impl ArtilleryMember {
         self.remote_host
     }
 
+    pub fn incarnation_number(&self) -> u64 {
+        self.incarnation_number
+    }
+
+    pub fn metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, metadata: BTreeMap<String, String>) {
+        self.metadata = metadata;
+    }
+
     pub fn is_remote(&self) -> bool {
         self.remote_host.is_some()
     }
@@ -85,7 +105,7 @@ impl ArtilleryMember {
     }
 
     pub fn state_change_older_than(&self, duration: Duration) -> bool {
-        self.last_state_change + duration < Utc::now()
+        self.last_state_change + duration < runtime::now()
     }
 
     pub fn state(&self) -> ArtilleryMemberState {
@@ -95,7 +115,7 @@ impl ArtilleryMember {
     pub fn set_state(&mut self, state: ArtilleryMemberState) {
         if self.member_state != state {
             self.member_state = state;
-            self.last_state_change = Utc::now();
+            self.last_state_change = runtime::now();
         }
     }
 
@@ -106,20 +126,59 @@ impl ArtilleryMember {
         }
     }
 
+    ///
+    /// Times the state change of gossiped data by our own clock, the sender's may be off.
+    /// The time of `known` is kept if the state stayed the same.
+    pub fn observed_after(self, known: Option<&ArtilleryMember>) -> ArtilleryMember {
+        let last_state_change = match known {
+            Some(member) if member.member_state == self.member_state => member.last_state_change,
+            Some(_) | None => runtime::now(),
+        };
+
+        ArtilleryMember {
+            last_state_change,
+            ..self
+        }
+    }
+
     pub fn reincarnate(&mut self) {
         self.incarnation_number += 1
     }
+
+    ///
+    /// Continues above the incarnation of an earlier run, so that our state wins over it.
+    pub fn resume_after(&mut self, incarnation_number: u64) {
+        self.incarnation_number = self
+            .incarnation_number
+            .max(incarnation_number.saturating_add(1));
+    }
 }
 
 impl ArtilleryStateChange {
     pub fn new(member: ArtilleryMember) -> ArtilleryStateChange {
-        ArtilleryStateChange { member }
+        ArtilleryStateChange {
+            member,
+            suspector: None,
+        }
+    }
+
+    ///
+    /// State change of a suspected member, naming the member that suspects it.
+    pub fn suspected(member: ArtilleryMember, suspector: Uuid) -> ArtilleryStateChange {
+        ArtilleryStateChange {
+            member,
+            suspector: Some(suspector),
+        }
     }
 
     pub fn member(&self) -> &ArtilleryMember {
         &self.member
     }
 
+    pub fn suspector(&self) -> Option<Uuid> {
+        self.suspector
+    }
+
     pub fn update(&mut self, member: ArtilleryMember) {
         self.member = member
     }
@@ -157,9 +216,10 @@ impl Debug for ArtilleryMember {
             .field("incarnation_number", &self.incarnation_number)
             .field("host", &self.host_key)
             .field("state", &self.member_state)
+            .field("metadata", &self.metadata)
             .field(
                 "drift_time_ms",
-                &(Utc::now() - self.last_state_change).num_milliseconds(),
+                &(runtime::now() - self.last_state_change).num_milliseconds(),
             )
             .field(
                 "remote_host",
@@ -190,8 +250,12 @@ pub fn most_uptodate_member_data<'a>(
         (ArtilleryMemberState::Alive, i, ArtilleryMemberState::Alive, j) => i > j,
         (ArtilleryMemberState::Suspect, i, ArtilleryMemberState::Suspect, j) => i > j,
         (ArtilleryMemberState::Suspect, i, ArtilleryMemberState::Alive, j) => i >= j,
-        (ArtilleryMemberState::Down, _, ArtilleryMemberState::Alive, _) => true,
-        (ArtilleryMemberState::Down, _, ArtilleryMemberState::Suspect, _) => true,
+        // Only the member itself raises its incarnation, so a newer one proves it outlived
+        // the death gossip, e.g. across a partition. Otherwise Down is final.
+        (ArtilleryMemberState::Alive, i, ArtilleryMemberState::Down, j) => i > j,
+        (ArtilleryMemberState::Suspect, i, ArtilleryMemberState::Down, j) => i > j,
+        (ArtilleryMemberState::Down, i, ArtilleryMemberState::Alive, j) => i >= j,
+        (ArtilleryMemberState::Down, i, ArtilleryMemberState::Suspect, j) => i >= j,
         (ArtilleryMemberState::Left, _, _, _) => true,
         _ => false,
     };
@@ -207,8 +271,9 @@ pub fn most_uptodate_member_data<'a>(
 mod test {
     use std::str::FromStr;
 
-    use super::{ArtilleryMember, ArtilleryMemberState};
+    use super::{most_uptodate_member_data, ArtilleryMember, ArtilleryMemberState};
     use chrono::{Duration, Utc};
+    use std::collections::BTreeMap;
 
     use uuid;
 
@@ -220,6 +285,9 @@ mod test {
             incarnation_number: 123,
             member_state: ArtilleryMemberState::Alive,
             last_state_change: Utc::now() - Duration::days(1),
+            metadata: vec![("zone".to_string(), "eu-west-1a".to_string())]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>(),
         };
 
         let encoded = bincode::serialize(&member).unwrap();
@@ -232,4 +300,24 @@ mod test {
 
         assert_eq!(decoded, member);
     }
+
+    #[test]
+    fn test_newer_incarnation_overrides_down() {
+        let host_key = uuid::Uuid::new_v4();
+        let addr = FromStr::from_str("127.0.0.1:1337").unwrap();
+        let down = ArtilleryMember::new(host_key, addr, 3, ArtilleryMemberState::Down);
+        let stale = ArtilleryMember::new(host_key, addr, 3, ArtilleryMemberState::Alive);
+        let refuted = ArtilleryMember::new(host_key, addr, 4, ArtilleryMemberState::Alive);
+        let stale_suspect = ArtilleryMember::new(host_key, addr, 3, ArtilleryMemberState::Suspect);
+        let suspect = ArtilleryMember::new(host_key, addr, 4, ArtilleryMemberState::Suspect);
+
+        assert_eq!(most_uptodate_member_data(&stale, &down), &down);
+        assert_eq!(most_uptodate_member_data(&down, &stale), &down);
+        assert_eq!(most_uptodate_member_data(&refuted, &down), &refuted);
+        assert_eq!(most_uptodate_member_data(&down, &refuted), &refuted);
+        assert_eq!(most_uptodate_member_data(&stale_suspect, &down), &down);
+        assert_eq!(most_uptodate_member_data(&down, &stale_suspect), &down);
+        assert_eq!(most_uptodate_member_data(&suspect, &down), &suspect);
+        assert_eq!(most_uptodate_member_data(&down, &suspect), &suspect);
+    }
 }
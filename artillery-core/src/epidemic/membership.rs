@@ -1,18 +1,24 @@
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
 
-use chrono::Duration;
+use chrono::{DateTime, Duration, Utc};
 use uuid::Uuid;
 
 use super::member::{ArtilleryMember, ArtilleryMemberState, ArtilleryStateChange};
+use super::suspicion::{Suspicion, SuspicionBounds};
 use crate::epidemic::member;
-use bastion_utils::math;
+use crate::epidemic::runtime;
 
 use kaos::flunk;
 
+// Reaped member's last incarnation and the time it was reaped at.
+type Tombstone = (u64, DateTime<Utc>);
+
 pub struct ArtilleryMemberList {
     members: Vec<ArtilleryMember>,
+    suspicions: HashMap<Uuid, Suspicion>,
+    tombstones: HashMap<Uuid, Tombstone>,
     periodic_index: usize,
 }
 
@@ -20,6 +26,8 @@ impl ArtilleryMemberList {
     pub fn new(current: ArtilleryMember) -> Self {
         ArtilleryMemberList {
             members: vec![current],
+            suspicions: HashMap::new(),
+            tombstones: HashMap::new(),
             periodic_index: 0,
         }
     }
@@ -32,6 +40,12 @@ impl ArtilleryMemberList {
             .collect()
     }
 
+    ///
+    /// Every member, the ones which left included.
+    pub fn all_nodes(&self) -> Vec<ArtilleryMember> {
+        self.members.clone()
+    }
+
     pub fn to_map(&self) -> HashMap<Uuid, ArtilleryMember> {
         self.members
             .iter()
@@ -56,6 +70,16 @@ impl ArtilleryMemberList {
         myself.clone()
     }
 
+    ///
+    /// Counters gossip about our failure with an incarnation above the accused one.
+    fn refute(&mut self, accused_incarnation: u64) -> ArtilleryMember {
+        let myself = self.mut_myself();
+        let incarnation_number = myself.incarnation_number().max(accused_incarnation);
+        myself.resume_after(incarnation_number);
+
+        myself.clone()
+    }
+
     pub fn leave(&mut self) -> ArtilleryMember {
         let myself = self.mut_myself();
         myself.set_state(ArtilleryMemberState::Left);
@@ -64,9 +88,19 @@ impl ArtilleryMemberList {
         myself.clone()
     }
 
+    ///
+    /// Replaces our metadata. New incarnation makes it override the old one everywhere.
+    pub fn set_metadata(&mut self, metadata: BTreeMap<String, String>) -> ArtilleryMember {
+        let myself = self.mut_myself();
+        myself.set_metadata(metadata);
+        myself.reincarnate();
+
+        myself.clone()
+    }
+
     pub fn next_random_member(&mut self) -> Option<ArtilleryMember> {
         if self.periodic_index == 0 {
-            math::shuffle_linear(&mut self.members);
+            runtime::shuffle(&mut self.members);
         }
 
         let other_members: Vec<_> = self.members.iter().filter(|&m| m.is_remote()).collect();
@@ -80,41 +114,114 @@ impl ArtilleryMemberList {
         }
     }
 
+    ///
+    /// Moves timed out members to `Suspect` and starts their suspicion timers.
+    /// Suspected members whose suspicion timer expired are moved to `Down`.
     pub fn time_out_nodes(
         &mut self,
         expired_hosts: &HashSet<SocketAddr>,
+        bounds: &SuspicionBounds,
     ) -> (Vec<ArtilleryMember>, Vec<ArtilleryMember>) {
         let mut suspect_members = Vec::new();
         let mut down_members = Vec::new();
 
+        let my_host_key = self.mut_myself().host_key();
+        let suspicions = &mut self.suspicions;
+
         for member in &mut self.members {
             if let Some(remote_host) = member.remote_host() {
-                if !expired_hosts.contains(&remote_host) {
-                    continue;
-                }
-
                 match member.state() {
                     ArtilleryMemberState::Alive => {
+                        if !expired_hosts.contains(&remote_host) {
+                            continue;
+                        }
+
                         member.set_state(ArtilleryMemberState::Suspect);
+                        suspicions.insert(member.host_key(), Suspicion::new(my_host_key, *bounds));
                         suspect_members.push(member.clone());
                     }
-                    // TODO: Config suspect timeout
-                    ArtilleryMemberState::Suspect
-                        if member.state_change_older_than(Duration::seconds(3)) =>
-                    {
-                        member.set_state(ArtilleryMemberState::Down);
-                        down_members.push(member.clone());
+                    ArtilleryMemberState::Suspect => {
+                        let suspicion = suspicions
+                            .entry(member.host_key())
+                            .or_insert_with(|| Suspicion::new(my_host_key, *bounds));
+
+                        if suspicion.is_expired() {
+                            member.set_state(ArtilleryMemberState::Down);
+                            down_members.push(member.clone());
+                        }
                     }
-                    ArtilleryMemberState::Suspect
-                    | ArtilleryMemberState::Down
-                    | ArtilleryMemberState::Left => {}
+                    ArtilleryMemberState::Down | ArtilleryMemberState::Left => {}
                 }
             }
         }
 
+        let members = &self.members;
+        self.suspicions.retain(|host_key, _| {
+            members
+                .iter()
+                .any(|m| m.host_key() == *host_key && m.state() == ArtilleryMemberState::Suspect)
+        });
+
         (suspect_members, down_members)
     }
 
+    ///
+    /// Removes the members which stayed `Down` or `Left` longer than their reap timeout.
+    /// Reaped members leave a tombstone behind, so stale gossip can't bring them back
+    /// until the tombstone expires.
+    pub fn reap(
+        &mut self,
+        down_timeout: Duration,
+        left_timeout: Duration,
+        tombstone_timeout: Duration,
+    ) -> Vec<ArtilleryMember> {
+        let now = runtime::now();
+        self.tombstones
+            .retain(|_, &mut (_, reaped_at)| reaped_at + tombstone_timeout >= now);
+
+        let (reaped, kept): (Vec<_>, Vec<_>) =
+            self.members.drain(..).partition(|m| match m.state() {
+                ArtilleryMemberState::Down => {
+                    m.is_remote() && m.state_change_older_than(down_timeout)
+                }
+                ArtilleryMemberState::Left => {
+                    m.is_remote() && m.state_change_older_than(left_timeout)
+                }
+                ArtilleryMemberState::Alive | ArtilleryMemberState::Suspect => false,
+            });
+        self.members = kept;
+
+        for member in &reaped {
+            self.suspicions.remove(&member.host_key());
+            self.tombstones
+                .insert(member.host_key(), (member.incarnation_number(), now));
+        }
+
+        reaped
+    }
+
+    ///
+    /// Member that started the suspicion of the given member, if it is suspected.
+    pub fn suspector_of(&self, host_key: &Uuid) -> Option<Uuid> {
+        self.suspicions.get(host_key).map(Suspicion::origin)
+    }
+
+    ///
+    /// Returns `true` if the member was reaped and the given data isn't newer than
+    /// what we knew when reaping it.
+    pub fn is_tombstoned(&self, member: &ArtilleryMember) -> bool {
+        match self.tombstones.get(&member.host_key()) {
+            Some(&(incarnation, _)) => member.incarnation_number() <= incarnation,
+            None => false,
+        }
+    }
+
+    ///
+    /// Lets a reaped member back in regardless of its incarnation.
+    pub fn forget_tombstone(&mut self, host_key: &Uuid) {
+        self.tombstones.remove(host_key);
+    }
+
     pub fn mark_node_alive(&mut self, src_addr: &SocketAddr) -> Option<ArtilleryMember> {
         for member in &mut self.members {
             if member.remote_host() == Some(*src_addr)
@@ -129,26 +236,43 @@ impl ArtilleryMemberList {
         None
     }
 
+    ///
+    /// Merges gossiped state changes into the member list. Returns the new members, the
+    /// members which changed their state and the ones which only updated their metadata.
     pub fn apply_state_changes(
         &mut self,
         state_changes: Vec<ArtilleryStateChange>,
         from: &SocketAddr,
-    ) -> (Vec<ArtilleryMember>, Vec<ArtilleryMember>) {
-        let mut current_members = self.to_map();
+        sender: &Uuid,
+        bounds: &SuspicionBounds,
+    ) -> (
+        Vec<ArtilleryMember>,
+        Vec<ArtilleryMember>,
+        Vec<ArtilleryMember>,
+    ) {
+        // Ordered, so that the member list comes out the same for the same changes.
+        let mut current_members: BTreeMap<Uuid, ArtilleryMember> =
+            self.to_map().into_iter().collect();
 
         let mut changed_nodes = Vec::new();
         let mut new_nodes = Vec::new();
+        let mut updated_nodes = Vec::new();
 
         let my_host_key = self.mut_myself().host_key();
 
         for state_change in state_changes {
             let new_member_data = state_change.member();
+            if self.is_tombstoned(new_member_data) {
+                continue;
+            }
+
             let old_member_data = current_members.entry(new_member_data.host_key());
 
             if new_member_data.host_key() == my_host_key {
                 if new_member_data.state() != ArtilleryMemberState::Alive {
-                    let myself = self.reincarnate_self();
-                    changed_nodes.push(myself.clone());
+                    let myself = self.refute(new_member_data.incarnation_number());
+                    current_members.insert(my_host_key, myself.clone());
+                    changed_nodes.push(myself);
                 }
             } else {
                 match old_member_data {
@@ -159,16 +283,62 @@ impl ArtilleryMemberList {
                             .remote_host()
                             .or_else(|| entry.get().remote_host())
                             .unwrap();
-                        let new_member = new_member.member_by_changing_host(new_host);
+                        let new_member = new_member
+                            .member_by_changing_host(new_host)
+                            .observed_after(Some(entry.get()));
+
+                        if new_member.state() == entry.get().state() {
+                            // Every other member suspecting it on its own counts as an
+                            // independent confirmation. Members merely passing the
+                            // suspicion along name its original suspector instead.
+                            let same_suspicion = new_member.state()
+                                == ArtilleryMemberState::Suspect
+                                && new_member_data.state() == ArtilleryMemberState::Suspect
+                                && new_member_data.incarnation_number()
+                                    == entry.get().incarnation_number();
+
+                            if same_suspicion {
+                                if let (Some(suspector), Some(suspicion)) = (
+                                    state_change.suspector(),
+                                    self.suspicions.get_mut(&new_member.host_key()),
+                                ) {
+                                    suspicion.confirm(suspector);
+                                }
+                            }
+
+                            // Newer incarnation in the same state carries new metadata.
+                            let updated = new_member.incarnation_number()
+                                != entry.get().incarnation_number()
+                                || new_member.metadata() != entry.get().metadata();
+
+                            if updated {
+                                entry.insert(new_member.clone());
+                                updated_nodes.push(new_member);
+                            }
+                        } else {
+                            if new_member.state() == ArtilleryMemberState::Suspect {
+                                let suspector = state_change.suspector().unwrap_or(*sender);
+                                self.suspicions.insert(
+                                    new_member.host_key(),
+                                    Suspicion::new(suspector, *bounds),
+                                );
+                            }
 
-                        if new_member.state() != entry.get().state() {
                             entry.insert(new_member.clone());
                             changed_nodes.push(new_member);
                         }
                     }
                     Entry::Vacant(entry) => {
                         let new_host = new_member_data.remote_host().unwrap_or(*from);
-                        let new_member = new_member_data.member_by_changing_host(new_host);
+                        let new_member = new_member_data
+                            .member_by_changing_host(new_host)
+                            .observed_after(None);
+
+                        if new_member.state() == ArtilleryMemberState::Suspect {
+                            let suspector = state_change.suspector().unwrap_or(*sender);
+                            self.suspicions
+                                .insert(new_member.host_key(), Suspicion::new(suspector, *bounds));
+                        }
 
                         entry.insert(new_member.clone());
                         new_nodes.push(new_member);
@@ -179,7 +349,7 @@ impl ArtilleryMemberList {
 
         self.members = current_members.values().cloned().collect();
 
-        (new_nodes, changed_nodes)
+        (new_nodes, changed_nodes, updated_nodes)
     }
 
     ///
@@ -205,15 +375,63 @@ impl ArtilleryMemberList {
             })
             .collect();
 
-        math::shuffle_linear(&mut possible_members);
+        runtime::shuffle(&mut possible_members);
 
         possible_members.iter().take(host_count).cloned().collect()
     }
 
-    pub fn has_member(&self, remote_host: &SocketAddr) -> bool {
+    ///
+    /// Whether any member, whatever its state, is known at the address.
+    pub fn has_member_at(&self, addr: &SocketAddr) -> bool {
+        self.members.iter().any(|m| m.remote_host() == Some(*addr))
+    }
+
+    ///
+    /// Address of a random alive member other than us, used as push/pull partner.
+    pub fn random_alive_host(&self) -> Option<SocketAddr> {
+        let mut alive_hosts: Vec<_> = self
+            .members
+            .iter()
+            .filter(|m| m.state() == ArtilleryMemberState::Alive && m.is_remote())
+            .filter_map(ArtilleryMember::remote_host)
+            .collect();
+
+        runtime::shuffle(&mut alive_hosts);
+
+        alive_hosts.first().cloned()
+    }
+
+    ///
+    /// Addresses of the members which weren't found down or gone.
+    pub fn reachable_hosts(&self) -> Vec<SocketAddr> {
         self.members
             .iter()
-            .any(|m| m.remote_host() == Some(*remote_host))
+            .filter(|m| match m.state() {
+                ArtilleryMemberState::Alive | ArtilleryMemberState::Suspect => true,
+                ArtilleryMemberState::Down | ArtilleryMemberState::Left => false,
+            })
+            .filter_map(ArtilleryMember::remote_host)
+            .collect()
+    }
+
+    pub fn has_member(&self, host_key: &Uuid) -> bool {
+        self.members.iter().any(|m| m.host_key() == *host_key)
+    }
+
+    ///
+    /// Moves a member over to a new address, returning its updated data.
+    pub fn change_address(
+        &mut self,
+        host_key: &Uuid,
+        remote_host: SocketAddr,
+    ) -> Option<ArtilleryMember> {
+        let member = self
+            .members
+            .iter_mut()
+            .find(|m| m.host_key() == *host_key && m.is_remote())?;
+        *member = member.member_by_changing_host(remote_host);
+
+        Some(member.clone())
     }
 
     pub fn add_member(&mut self, member: ArtilleryMember) {
@@ -236,3 +454,176 @@ impl ArtilleryMemberList {
         Some(member[0].clone())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::ArtilleryMemberList;
+    use crate::epidemic::cluster_config::ClusterConfig;
+    use crate::epidemic::member::{ArtilleryMember, ArtilleryMemberState, ArtilleryStateChange};
+    use crate::epidemic::runtime;
+    use crate::epidemic::suspicion::SuspicionBounds;
+    use chrono::{Duration, Utc};
+    use std::net::SocketAddr;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_reaped_member_is_not_resurrected_by_stale_gossip() {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1337));
+        let sender = Uuid::new_v4();
+        let bounds = SuspicionBounds::new(&ClusterConfig::default(), 2);
+        let mut members = ArtilleryMemberList::new(ArtilleryMember::current(Uuid::new_v4()));
+
+        let down = ArtilleryMember::new(Uuid::new_v4(), addr, 3, ArtilleryMemberState::Down);
+        members.apply_state_changes(
+            vec![ArtilleryStateChange::new(down.clone())],
+            &addr,
+            &sender,
+            &bounds,
+        );
+
+        let hour = Duration::hours(1);
+        assert!(members.reap(hour, hour, hour).is_empty());
+
+        let reaped = members.reap(Duration::milliseconds(-1), hour, hour);
+        assert_eq!(reaped, vec![down.clone()]);
+        assert!(members.get_member(&down.host_key()).is_none());
+
+        let stale = ArtilleryMember::new(down.host_key(), addr, 3, ArtilleryMemberState::Alive);
+        let (new, _, _) = members.apply_state_changes(
+            vec![ArtilleryStateChange::new(stale)],
+            &addr,
+            &sender,
+            &bounds,
+        );
+        assert!(new.is_empty());
+
+        let rejoined = ArtilleryMember::new(down.host_key(), addr, 4, ArtilleryMemberState::Alive);
+        let (new, _, _) = members.apply_state_changes(
+            vec![ArtilleryStateChange::new(rejoined)],
+            &addr,
+            &sender,
+            &bounds,
+        );
+        assert_eq!(new.len(), 1);
+
+        // Restarted without its incarnation, it only gets back in through direct contact.
+        let gone = ArtilleryMember::new(Uuid::new_v4(), addr, 5, ArtilleryMemberState::Down);
+        members.apply_state_changes(
+            vec![ArtilleryStateChange::new(gone.clone())],
+            &addr,
+            &sender,
+            &bounds,
+        );
+        assert_eq!(
+            members.reap(Duration::milliseconds(-1), hour, hour),
+            vec![gone.clone()]
+        );
+        let restarted = ArtilleryMember::new(gone.host_key(), addr, 0, ArtilleryMemberState::Alive);
+        assert!(members.is_tombstoned(&restarted));
+        members.forget_tombstone(&restarted.host_key());
+        let (new, _, _) = members.apply_state_changes(
+            vec![ArtilleryStateChange::new(restarted)],
+            &addr,
+            &sender,
+            &bounds,
+        );
+        assert_eq!(new.len(), 1);
+    }
+
+    #[test]
+    fn test_reaping_follows_the_local_clock() {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1337));
+        let sender = Uuid::new_v4();
+        let bounds = SuspicionBounds::new(&ClusterConfig::default(), 2);
+        let mut members = ArtilleryMemberList::new(ArtilleryMember::current(Uuid::new_v4()));
+
+        // Sender's clock is two hours behind ours.
+        let down = ArtilleryMember::new(Uuid::new_v4(), addr, 3, ArtilleryMemberState::Down);
+        runtime::advance(Duration::hours(2));
+        members.apply_state_changes(
+            vec![ArtilleryStateChange::new(down)],
+            &addr,
+            &sender,
+            &bounds,
+        );
+
+        let hour = Duration::hours(1);
+        assert!(members.reap(hour, hour, hour).is_empty());
+        runtime::advance(Duration::minutes(61));
+        assert_eq!(members.reap(hour, hour, hour).len(), 1);
+    }
+
+    #[test]
+    fn test_refutation_outbids_the_accusation() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1337));
+        let sender = Uuid::new_v4();
+        let bounds = SuspicionBounds::new(&ClusterConfig::default(), 2);
+        let mut me = ArtilleryMember::current(Uuid::new_v4());
+        me.resume_after(4);
+        let mut members = ArtilleryMemberList::new(me.clone());
+
+        let accused = ArtilleryMember::new(me.host_key(), addr, 7, ArtilleryMemberState::Suspect);
+        let (_, changed, _) = members.apply_state_changes(
+            vec![ArtilleryStateChange::new(accused)],
+            &addr,
+            &sender,
+            &bounds,
+        );
+
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].incarnation_number(), 8);
+        assert_eq!(
+            members
+                .get_member(&me.host_key())
+                .map(|m| m.incarnation_number()),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn test_passed_on_suspicion_is_not_a_confirmation() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1337));
+        let bounds = SuspicionBounds::new(&ClusterConfig::default(), 10);
+        let mut members = ArtilleryMemberList::new(ArtilleryMember::current(Uuid::new_v4()));
+
+        let alive = ArtilleryMember::new(Uuid::new_v4(), addr, 1, ArtilleryMemberState::Alive);
+        let suspect =
+            ArtilleryMember::new(alive.host_key(), addr, 1, ArtilleryMemberState::Suspect);
+        members.add_member(alive.clone());
+
+        let (suspector, relays, other) = (
+            Uuid::new_v4(),
+            [Uuid::new_v4(), Uuid::new_v4()],
+            Uuid::new_v4(),
+        );
+        members.apply_state_changes(
+            vec![ArtilleryStateChange::suspected(suspect.clone(), suspector)],
+            &addr,
+            &suspector,
+            &bounds,
+        );
+        for relay in &relays {
+            members.apply_state_changes(
+                vec![ArtilleryStateChange::suspected(suspect.clone(), suspector)],
+                &addr,
+                relay,
+                &bounds,
+            );
+        }
+
+        let confirmations =
+            |members: &ArtilleryMemberList| members.suspicions[&alive.host_key()].confirmations();
+        assert_eq!(members.suspector_of(&alive.host_key()), Some(suspector));
+        assert_eq!(confirmations(&members), 0);
+
+        members.apply_state_changes(
+            vec![ArtilleryStateChange::suspected(suspect, other)],
+            &addr,
+            &relays[0],
+            &bounds,
+        );
+        assert_eq!(confirmations(&members), 1);
+    }
+}
@@ -1,16 +1,50 @@
 // As you swim lazily through the milieu,
 // The secrets of the world will infect you.
 
+pub mod awareness;
+pub mod broadcast;
 pub mod cluster;
 pub mod cluster_config;
+pub mod coordinate;
+pub mod delegate;
+pub mod dissemination;
+pub mod keyring;
 pub mod member;
 pub mod membership;
+pub mod network;
+pub mod payload;
+pub mod quarantine;
+pub mod query;
+pub(crate) mod runtime;
+pub mod seeds;
+pub mod simulation;
+pub mod snapshot;
 pub mod state;
+pub mod stream;
+pub mod suspicion;
+pub mod transport;
+pub mod wire;
 
 pub mod prelude {
+    pub use super::awareness::*;
+    pub use super::broadcast::*;
     pub use super::cluster::*;
     pub use super::cluster_config::*;
+    pub use super::coordinate::*;
+    pub use super::delegate::*;
+    pub use super::dissemination::*;
+    pub use super::keyring::*;
     pub use super::member::*;
     pub use super::membership::*;
+    pub use super::network::*;
+    pub use super::payload::*;
+    pub use super::quarantine::*;
+    pub use super::query::*;
+    pub use super::seeds::*;
+    pub use super::simulation::*;
+    pub use super::snapshot::*;
     pub use super::state::*;
+    pub use super::stream::*;
+    pub use super::suspicion::*;
+    pub use super::transport::*;
 }
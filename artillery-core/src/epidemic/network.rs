@@ -0,0 +1,131 @@
+use crate::errors::*;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+
+// Pending connections of the stream listener.
+const STREAM_BACKLOG: i32 = 128;
+
+///
+/// Binds the gossip socket. Binding the IPv6 unspecified address gives a dual-stack
+/// socket, which serves IPv4 peers through their v4-mapped addresses.
+pub fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(domain_of(addr), Type::dgram(), Some(Protocol::udp()))?;
+    prepare(&socket, addr)?;
+    socket.bind(&SockAddr::from(addr))?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into_udp_socket())
+}
+
+///
+/// Binds the stream listener, dual-stack under the same rules as [`bind_udp`].
+pub fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(domain_of(addr), Type::stream(), Some(Protocol::tcp()))?;
+    prepare(&socket, addr)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SockAddr::from(addr))?;
+    socket.listen(STREAM_BACKLOG)?;
+
+    Ok(socket.into_tcp_listener())
+}
+
+fn domain_of(addr: SocketAddr) -> Domain {
+    match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    }
+}
+
+fn prepare(socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+    match addr.ip() {
+        IpAddr::V6(ip) if ip.is_unspecified() => socket.set_only_v6(false),
+        IpAddr::V4(_) | IpAddr::V6(_) => Ok(()),
+    }
+}
+
+///
+/// Address as it is kept in the member list, v4-mapped IPv6 addresses of dual-stack
+/// sockets are turned back into IPv4 ones.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v4_mapped(v6.ip()) {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+///
+/// Address to send to from a socket bound to `local`, IPv6 sockets reach IPv4 peers
+/// through their v4-mapped addresses.
+pub fn outgoing(target: SocketAddr, local: SocketAddr) -> SocketAddr {
+    match (target, local) {
+        (SocketAddr::V4(v4), SocketAddr::V6(_)) => {
+            SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port())
+        }
+        (SocketAddr::V4(_), SocketAddr::V4(_))
+        | (SocketAddr::V6(_), SocketAddr::V4(_))
+        | (SocketAddr::V6(_), SocketAddr::V6(_)) => target,
+    }
+}
+
+fn v4_mapped(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.octets() {
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => Some(Ipv4Addr::new(a, b, c, d)),
+        _ => None,
+    }
+}
+
+///
+/// Rejects advertise addresses other members can't reach us at.
+pub fn validate_advertise_addr(addr: SocketAddr) -> Result<()> {
+    let routable = match canonical(addr).ip() {
+        IpAddr::V4(ip) => !(ip.is_unspecified() || ip.is_multicast() || ip.is_broadcast()),
+        IpAddr::V6(ip) => !(ip.is_unspecified() || ip.is_multicast()),
+    };
+
+    if !routable || addr.port() == 0 {
+        bail!(
+            ArtilleryError::Config,
+            format!("advertise address {} is not routable", addr)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{canonical, outgoing, validate_advertise_addr};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_v4_mapped_addresses_are_normalized() {
+        let v4: SocketAddr = "10.0.0.7:27845".parse().unwrap();
+        let mapped: SocketAddr = "[::ffff:10.0.0.7]:27845".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::7]:27845".parse().unwrap();
+        let dual_stack: SocketAddr = "[::]:27845".parse().unwrap();
+
+        assert_eq!(canonical(mapped), v4);
+        assert_eq!(canonical(v6), v6);
+        assert_eq!(outgoing(v4, dual_stack), mapped);
+        assert_eq!(outgoing(v6, dual_stack), v6);
+    }
+
+    #[test]
+    fn test_unroutable_advertise_addresses_are_rejected() {
+        for addr in &[
+            "0.0.0.0:27845",
+            "[::]:27845",
+            "224.0.0.1:27845",
+            "10.0.0.7:0",
+        ] {
+            assert!(validate_advertise_addr(addr.parse().unwrap()).is_err());
+        }
+
+        assert!(validate_advertise_addr("10.0.0.7:27845".parse().unwrap()).is_ok());
+        assert!(validate_advertise_addr("[2001:db8::7]:27845".parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,113 @@
+use crate::epidemic::member::ArtilleryMember;
+use crate::errors::*;
+use crate::events::EventSender;
+use serde::de::DeserializeOwned;
+use serde::*;
+use std::collections::HashMap;
+
+///
+/// Payload received on a topic along with the member which sent it.
+pub type PayloadEvent = (ArtilleryMember, ArtilleryPayload);
+
+///
+/// Binary payload tagged with the topic it is routed by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArtilleryPayload {
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+impl ArtilleryPayload {
+    pub fn new<T: AsRef<str>>(topic: T, data: Vec<u8>) -> Self {
+        ArtilleryPayload {
+            topic: topic.as_ref().to_string(),
+            data,
+        }
+    }
+
+    ///
+    /// Serializes the value with bincode, see [`ArtilleryPayload::decode`].
+    pub fn encode<T: AsRef<str>, V: Serialize>(topic: T, value: &V) -> Result<Self> {
+        Ok(Self::new(topic, bincode::serialize(value)?))
+    }
+
+    pub fn decode<V: DeserializeOwned>(&self) -> Result<V> {
+        Ok(bincode::deserialize(&self.data)?)
+    }
+}
+
+///
+/// Per-topic subscriptions to the incoming payloads.
+///
+/// Payloads on a subscribed topic go to its subscribers only, everything else
+/// is left to the general event stream.
+#[derive(Debug, Default)]
+pub struct PayloadRouter {
+    subscribers: HashMap<String, Vec<EventSender<PayloadEvent>>>,
+}
+
+impl PayloadRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, topic: String, subscriber: EventSender<PayloadEvent>) {
+        self.subscribers.entry(topic).or_default().push(subscriber);
+    }
+
+    ///
+    /// Hands the payload to the subscribers of its topic. Returns it back if nobody
+    /// is listening, subscriptions whose receivers are gone are dropped on the way.
+    pub fn route(
+        &mut self,
+        member: ArtilleryMember,
+        payload: ArtilleryPayload,
+    ) -> Option<PayloadEvent> {
+        let subscribers = match self.subscribers.get_mut(&payload.topic) {
+            Some(subscribers) => subscribers,
+            None => return Some((member, payload)),
+        };
+
+        subscribers.retain(|s| s.send((member.clone(), payload.clone())).is_ok());
+
+        if subscribers.is_empty() {
+            self.subscribers.remove(&payload.topic);
+            return Some((member, payload));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ArtilleryPayload, PayloadRouter};
+    use crate::epidemic::member::ArtilleryMember;
+    use crate::events::event_channel;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_payloads_are_routed_by_topic() {
+        let member = ArtilleryMember::current(Uuid::new_v4());
+        let mut router = PayloadRouter::new();
+
+        let (tx, rx) = event_channel();
+        router.subscribe("metrics".into(), tx);
+
+        let payload = ArtilleryPayload::encode("metrics", &(7_u32, "cpu")).unwrap();
+        assert!(router.route(member.clone(), payload).is_none());
+        let (_, received) = rx.try_recv().unwrap();
+        assert_eq!(
+            received.decode::<(u32, String)>().unwrap(),
+            (7, "cpu".into())
+        );
+
+        let other = ArtilleryPayload::new("logs", b"line".to_vec());
+        assert!(router.route(member.clone(), other).is_some());
+
+        // Dropped subscriptions fall back to the general stream.
+        drop(rx);
+        let late = ArtilleryPayload::new("metrics", Vec::new());
+        assert!(router.route(member, late).is_some());
+    }
+}
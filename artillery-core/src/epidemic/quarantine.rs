@@ -0,0 +1,148 @@
+use crate::epidemic::runtime;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+#[derive(Debug, Clone)]
+pub struct QuarantineConfig {
+    /// Bad packets within the window after which a source is quarantined, zero disables it
+    pub threshold: u32,
+    /// Time the bad packets of a source are counted over
+    pub window: Duration,
+    /// Time the packets of a quarantined source are dropped
+    pub period: Duration,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        QuarantineConfig {
+            threshold: 10,
+            window: Duration::minutes(1),
+            period: Duration::minutes(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Offender {
+    failures: u32,
+    first_failure: DateTime<Utc>,
+    quarantined_until: Option<DateTime<Utc>>,
+}
+
+///
+/// Bad packets per source, sources sending too many of them are quarantined.
+#[derive(Debug, Clone, Default)]
+pub struct Quarantine {
+    offenders: HashMap<SocketAddr, Offender>,
+}
+
+impl Quarantine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///
+    /// Records a bad packet from the source, returns its failures within the window.
+    pub fn record_failure(&mut self, source: SocketAddr, config: &QuarantineConfig) -> u32 {
+        let now = runtime::now();
+        let offender = self.offenders.entry(source).or_insert(Offender {
+            failures: 0,
+            first_failure: now,
+            quarantined_until: None,
+        });
+
+        // A served quarantine starts the count over as well.
+        let served = offender.quarantined_until.is_some_and(|until| until <= now);
+        if served || offender.first_failure + config.window < now {
+            offender.failures = 0;
+            offender.first_failure = now;
+            offender.quarantined_until = None;
+        }
+        offender.failures = offender.failures.saturating_add(1);
+
+        if config.threshold > 0
+            && offender.failures >= config.threshold
+            && offender.quarantined_until.is_none()
+        {
+            offender.quarantined_until = Some(now + config.period);
+        }
+
+        offender.failures
+    }
+
+    ///
+    /// Whether the packets of the source are to be dropped.
+    pub fn is_quarantined(&self, source: SocketAddr) -> bool {
+        let now = runtime::now();
+
+        self.offenders
+            .get(&source)
+            .and_then(|offender| offender.quarantined_until)
+            .is_some_and(|until| now < until)
+    }
+
+    ///
+    /// Forgets the sources whose quarantine and failure window are over.
+    pub fn expire(&mut self, config: &QuarantineConfig) {
+        let now = runtime::now();
+
+        self.offenders
+            .retain(|_, offender| match offender.quarantined_until {
+                Some(until) => now < until,
+                None => now <= offender.first_failure + config.window,
+            });
+    }
+
+    ///
+    /// Number of sources currently quarantined.
+    pub fn len(&self) -> usize {
+        let now = runtime::now();
+
+        self.offenders
+            .values()
+            .filter(|offender| offender.quarantined_until.is_some_and(|until| now < until))
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Quarantine, QuarantineConfig};
+    use crate::epidemic::runtime;
+    use chrono::{Duration, Utc};
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_repeated_failures_quarantine_the_source() {
+        let _guard = runtime::simulate(Utc::now(), 7);
+        let config = QuarantineConfig {
+            threshold: 3,
+            window: Duration::seconds(10),
+            period: Duration::minutes(1),
+        };
+        let source: SocketAddr = "10.0.0.7:27845".parse().unwrap();
+        let mut quarantine = Quarantine::new();
+
+        // Failures spread over more than the window don't add up.
+        assert_eq!(quarantine.record_failure(source, &config), 1);
+        assert_eq!(quarantine.record_failure(source, &config), 2);
+        runtime::advance(Duration::seconds(11));
+        assert_eq!(quarantine.record_failure(source, &config), 1);
+        assert!(!quarantine.is_quarantined(source));
+
+        quarantine.record_failure(source, &config);
+        quarantine.record_failure(source, &config);
+        assert!(quarantine.is_quarantined(source));
+        assert_eq!(quarantine.len(), 1);
+
+        runtime::advance(Duration::minutes(1));
+        quarantine.expire(&config);
+        assert!(!quarantine.is_quarantined(source));
+        assert!(quarantine.is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::epidemic::member::ArtilleryMember;
+use crate::epidemic::runtime;
+use crate::epidemic::state::ArtilleryClusterRequest;
+use crate::errors::*;
+use chrono::{DateTime, Utc};
+use mio::Waker;
+use serde::*;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+///
+/// Selects the members a query is asked to. Empty criteria match every member.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryFilter {
+    /// Host keys of the members to ask
+    pub members: Vec<Uuid>,
+    /// Metadata entries the members have to carry
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl QueryFilter {
+    pub fn matches(&self, member: &ArtilleryMember) -> bool {
+        let selected = self.members.is_empty() || self.members.contains(&member.host_key());
+
+        selected
+            && self
+                .metadata
+                .iter()
+                .all(|(key, value)| member.metadata().get(key) == Some(value))
+    }
+}
+
+///
+/// Query as it travels to the members.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArtilleryQuery {
+    pub id: u64,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub filter: QueryFilter,
+    pub timeout: Duration,
+}
+
+///
+/// Answers collected for a query, see [`crate::epidemic::cluster::Cluster::query`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryResponse {
+    /// Member received the query
+    Ack(ArtilleryMember),
+    /// Member answered the query
+    Response(ArtilleryMember, Vec<u8>),
+}
+
+///
+/// Query received from a member, answered with [`IncomingQuery::respond`].
+#[derive(Clone)]
+pub struct IncomingQuery {
+    pub from: ArtilleryMember,
+    pub name: String,
+    pub payload: Vec<u8>,
+    /// Answers arriving at the querying member after this are dropped
+    pub deadline: DateTime<Utc>,
+    id: u64,
+    // Local queries are answered without a round trip.
+    reply_to: Option<SocketAddr>,
+    request_tx: Sender<ArtilleryClusterRequest>,
+    waker: Arc<Waker>,
+}
+
+impl IncomingQuery {
+    pub(crate) fn new(
+        from: ArtilleryMember,
+        query: ArtilleryQuery,
+        reply_to: Option<SocketAddr>,
+        request_tx: Sender<ArtilleryClusterRequest>,
+        waker: Arc<Waker>,
+    ) -> Result<Self> {
+        let timeout = chrono::Duration::from_std(query.timeout)
+            .map_err(|e| ArtilleryError::NumericCast(e.to_string()))?;
+
+        Ok(IncomingQuery {
+            from,
+            name: query.name,
+            payload: query.payload,
+            deadline: runtime::now() + timeout,
+            id: query.id,
+            reply_to,
+            request_tx,
+            waker,
+        })
+    }
+
+    ///
+    /// Sends the answer back to the querying member. It has to fit into a single packet,
+    /// answers arriving after the query timed out are dropped.
+    pub fn respond(&self, response: Vec<u8>) -> Result<()> {
+        self.request_tx.send(ArtilleryClusterRequest::AnswerQuery(
+            self.reply_to,
+            self.id,
+            response,
+        ))?;
+        self.waker.wake()?;
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for IncomingQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncomingQuery")
+            .field("from", &self.from)
+            .field("name", &self.name)
+            .field("payload", &self.payload)
+            .field("deadline", &self.deadline)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::QueryFilter;
+    use crate::epidemic::member::ArtilleryMember;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_filter_matches_members_and_metadata() {
+        let mut member = ArtilleryMember::current(Uuid::new_v4());
+        member.set_metadata(
+            vec![("shard".to_string(), "12".to_string())]
+                .into_iter()
+                .collect(),
+        );
+
+        assert!(QueryFilter::default().matches(&member));
+
+        let mut filter = QueryFilter::default();
+        filter.metadata.insert("shard".into(), "12".into());
+        assert!(filter.matches(&member));
+
+        filter.members.push(Uuid::new_v4());
+        assert!(!filter.matches(&member));
+
+        filter.members.push(member.host_key());
+        filter.metadata.insert("zone".into(), "b".into());
+        assert!(!filter.matches(&member));
+    }
+}
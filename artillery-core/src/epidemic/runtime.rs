@@ -0,0 +1,108 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use std::cell::RefCell;
+use uuid::{Builder, Uuid, Variant, Version};
+
+// Clock and randomness of a simulation driving the current thread.
+struct Simulated {
+    now: DateTime<Utc>,
+    rng: StdRng,
+}
+
+thread_local! {
+    static SIMULATED: RefCell<Option<Simulated>> = const { RefCell::new(None) };
+}
+
+///
+/// Current time of the epidemic, the system clock unless it runs in a simulation.
+pub fn now() -> DateTime<Utc> {
+    SIMULATED
+        .with(|simulated| simulated.borrow().as_ref().map(|s| s.now))
+        .unwrap_or_else(Utc::now)
+}
+
+pub fn shuffle<T>(items: &mut [T]) {
+    with_rng(|rng| items.shuffle(rng));
+}
+
+pub fn random_f64() -> f64 {
+    with_rng(|rng| rng.gen())
+}
+
+pub fn random_uuid() -> Uuid {
+    let mut bytes = [0_u8; 16];
+    with_rng(|rng| rng.fill_bytes(&mut bytes));
+
+    Builder::from_bytes(bytes)
+        .set_variant(Variant::RFC4122)
+        .set_version(Version::Random)
+        .build()
+}
+
+fn with_rng<R, F: FnOnce(&mut dyn RngCore) -> R>(f: F) -> R {
+    SIMULATED.with(|current| match current.borrow_mut().as_mut() {
+        Some(simulated) => f(&mut simulated.rng),
+        None => f(&mut rand::thread_rng()),
+    })
+}
+
+///
+/// Puts the current thread under a simulated clock starting at `start` and an RNG
+/// seeded with `seed`, until the guard is dropped.
+pub(crate) fn simulate(start: DateTime<Utc>, seed: u64) -> SimulationGuard {
+    SIMULATED.with(|simulated| {
+        *simulated.borrow_mut() = Some(Simulated {
+            now: start,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    });
+
+    SimulationGuard { _private: () }
+}
+
+///
+/// Moves the simulated clock forward.
+pub(crate) fn advance(by: Duration) {
+    SIMULATED.with(|current| {
+        if let Some(simulated) = current.borrow_mut().as_mut() {
+            simulated.now += by;
+        }
+    });
+}
+
+pub(crate) struct SimulationGuard {
+    _private: (),
+}
+
+impl Drop for SimulationGuard {
+    fn drop(&mut self) {
+        SIMULATED.with(|simulated| *simulated.borrow_mut() = None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{advance, now, random_uuid, shuffle, simulate};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_simulation_is_reproducible() {
+        let start = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+
+        let run = || {
+            let _simulation = simulate(start, 7);
+            advance(Duration::seconds(5));
+            let mut items: Vec<u32> = (0..16).collect();
+            shuffle(&mut items);
+
+            (now(), items, random_uuid())
+        };
+
+        let (time, items, uuid) = run();
+        assert_eq!(time, start + Duration::seconds(5));
+        assert_eq!(run(), (time, items, uuid));
+        assert_ne!(now(), time);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::epidemic::network;
+use crate::epidemic::state::ArtilleryClusterRequest;
+use bastion_executor::blocking::spawn_blocking;
+use lightproc::proc_stack::ProcStack;
+use mio::Waker;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+///
+/// Resolves seed hosts given as `name:port` into the addresses of the seed nodes.
+pub trait SeedResolver: fmt::Debug + Send + Sync {
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+///
+/// Resolver of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl SeedResolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        Ok(host.to_socket_addrs()?.collect())
+    }
+}
+
+///
+/// Seed nodes given by their address or by a host name resolved to them.
+///
+/// Seeds are kept for good, so that a node which lost every member can join again.
+#[derive(Debug, Default)]
+pub struct SeedList {
+    addrs: Vec<SocketAddr>,
+    hosts: BTreeMap<String, Vec<SocketAddr>>,
+}
+
+impl SeedList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_addr(&mut self, addr: SocketAddr) {
+        if !self.addrs.contains(&addr) {
+            self.addrs.push(addr);
+        }
+    }
+
+    pub fn add_host(&mut self, host: String) {
+        self.hosts.entry(host).or_default();
+    }
+
+    pub fn hosts(&self) -> Vec<String> {
+        self.hosts.keys().cloned().collect()
+    }
+
+    ///
+    /// Replaces the addresses of the host. Returns the ones it didn't resolve to before.
+    pub fn update_host(&mut self, host: &str, resolved: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let addrs = match self.hosts.get_mut(host) {
+            Some(addrs) => addrs,
+            None => return Vec::new(),
+        };
+
+        let canonical: Vec<_> = resolved.into_iter().map(network::canonical).collect();
+        let added = canonical
+            .iter()
+            .filter(|addr| !addrs.contains(addr))
+            .cloned()
+            .collect();
+        *addrs = canonical;
+
+        added
+    }
+
+    pub fn all(&self) -> Vec<SocketAddr> {
+        let mut all = self.addrs.clone();
+        for addr in self.hosts.values().flatten() {
+            if !all.contains(addr) {
+                all.push(*addr);
+            }
+        }
+
+        all
+    }
+}
+
+///
+/// Resolves the host off the event loop, which gets the addresses as `SeedsResolved`.
+pub(crate) fn spawn_resolution(
+    resolver: Arc<dyn SeedResolver>,
+    host: String,
+    request_tx: Sender<ArtilleryClusterRequest>,
+    waker: Arc<Waker>,
+) {
+    let _resolution_handle = spawn_blocking(
+        async move {
+            match resolver.resolve(&host) {
+                Ok(addrs) => {
+                    let _ = request_tx.send(ArtilleryClusterRequest::SeedsResolved(host, addrs));
+                    let _ = waker.wake();
+                }
+                Err(e) => warn!("Failed to resolve seed host {}: {}", host, e),
+            }
+        },
+        ProcStack::default(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::SeedList;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_seed_hosts_track_their_addresses() {
+        let static_seed: SocketAddr = "10.0.0.1:27845".parse().unwrap();
+        let first: SocketAddr = "10.0.0.2:27845".parse().unwrap();
+        let second: SocketAddr = "10.0.0.3:27845".parse().unwrap();
+
+        let mut seeds = SeedList::new();
+        seeds.add_addr(static_seed);
+        seeds.add_host("seeds.artillery:27845".into());
+
+        assert_eq!(
+            seeds.update_host("seeds.artillery:27845", vec![first, static_seed]),
+            vec![first, static_seed]
+        );
+        assert_eq!(
+            seeds.update_host("seeds.artillery:27845", vec![second, first]),
+            vec![second]
+        );
+        assert!(seeds.update_host("unknown:27845", vec![first]).is_empty());
+
+        assert_eq!(seeds.all(), vec![static_seed, second, first]);
+    }
+}
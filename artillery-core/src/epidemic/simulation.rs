@@ -0,0 +1,912 @@
+use super::cluster_config::ClusterConfig;
+use super::member::{ArtilleryMember, ArtilleryMemberState};
+use super::network;
+use super::runtime::{self, SimulationGuard};
+use super::state::{
+    ArtilleryClusterEvent, ArtilleryClusterRequest, ArtilleryEpidemic, ArtilleryMemberEvent,
+    LeaveStatus,
+};
+use super::transport::{StreamAnswer, Transport};
+use crate::errors::*;
+use crate::events::{event_channel, EventReceiver};
+use crate::metrics::{Metrics, MetricsSnapshot};
+use chrono::{DateTime, Duration, Utc};
+use mio::{Registry, Token, Waker};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+// Port of the first node, the others follow it.
+const FIRST_PORT: u16 = 10_000;
+
+// What a datagram of the virtual network carries.
+enum Transfer {
+    Packet,
+    // Frame opening a stream exchange, along with where its answer goes
+    StreamRequest(StreamAnswer),
+    StreamAnswer(StreamAnswer),
+}
+
+struct Datagram {
+    from: SocketAddr,
+    to: SocketAddr,
+    bytes: Vec<u8>,
+    transfer: Transfer,
+}
+
+// Transport of a node, handing everything to the virtual network. Packets are
+// delivered by the simulation rather than read by the node.
+struct VirtualTransport {
+    addr: SocketAddr,
+    network: Sender<Datagram>,
+}
+
+impl VirtualTransport {
+    fn transfer(&self, bytes: Vec<u8>, target: SocketAddr, transfer: Transfer) {
+        // Network lives as long as the simulation, which outlives its nodes.
+        let _ = self.network.send(Datagram {
+            from: self.addr,
+            to: target,
+            bytes,
+            transfer,
+        });
+    }
+}
+
+impl Transport for VirtualTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.transfer(packet.to_vec(), target, Transfer::Packet);
+
+        Ok(packet.len())
+    }
+
+    fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        Err(io::ErrorKind::WouldBlock.into())
+    }
+
+    fn register(&mut self, _: &Registry, _: Token, _: Arc<Waker>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn supports_streams(&self) -> bool {
+        true
+    }
+
+    fn exchange_stream(
+        &self,
+        target: SocketAddr,
+        frame: Vec<u8>,
+        _: std::time::Duration,
+        on_answer: StreamAnswer,
+    ) {
+        self.transfer(frame, target, Transfer::StreamRequest(on_answer));
+    }
+}
+
+///
+/// Conditions of the virtual network, applied to every packet.
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    /// Probability of a packet getting lost
+    pub loss: f64,
+    /// Probability of a packet getting delivered twice
+    pub duplication: f64,
+    /// Delay of every packet
+    pub latency: Duration,
+    /// Random delay added on top of the latency, reordering the packets
+    pub jitter: Duration,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        NetworkConditions {
+            loss: 0.0,
+            duplication: 0.0,
+            latency: Duration::milliseconds(1),
+            jitter: Duration::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    /// Seed of the network and of the randomness within the nodes
+    pub seed: u64,
+    /// Simulated time which passes with every step
+    pub step: Duration,
+    pub network: NetworkConditions,
+    /// Configuration of every node, without seed resolution, snapshots and metrics
+    /// exporter
+    pub cluster: ClusterConfig,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            step: Duration::milliseconds(10),
+            network: NetworkConditions::default(),
+            cluster: ClusterConfig::default(),
+        }
+    }
+}
+
+///
+/// Event observed by a node during the simulation.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub at: DateTime<Utc>,
+    pub node: SocketAddr,
+    pub event: ArtilleryMemberEvent,
+}
+
+struct Node {
+    addr: SocketAddr,
+    host_key: Uuid,
+    state: ArtilleryEpidemic,
+    request_tx: Sender<ArtilleryClusterRequest>,
+    requests: Receiver<ArtilleryClusterRequest>,
+    events: EventReceiver<ArtilleryClusterEvent>,
+    metrics: Arc<Metrics>,
+    // Stream exchanges being answered, with the node which opened them.
+    answers: Vec<(SocketAddr, Receiver<Vec<u8>>, StreamAnswer)>,
+    up: bool,
+}
+
+///
+/// Runs many epidemics within the current thread over a virtual network, under a
+/// clock which only moves with the steps. Runs with the same seed are identical.
+/// There can be a single simulation per thread at a time.
+pub struct Simulation {
+    config: SimulationConfig,
+    nodes: Vec<Node>,
+    // Packets on the way, by delivery time and send order.
+    in_flight: BTreeMap<(DateTime<Utc>, u64), Datagram>,
+    sent: u64,
+    network_tx: Sender<Datagram>,
+    network_rx: Receiver<Datagram>,
+    rng: StdRng,
+    // Nodes cut off from the rest of the network.
+    partition: HashSet<SocketAddr>,
+    trace: Vec<Observation>,
+    _clock: SimulationGuard,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig) -> Self {
+        let clock = runtime::simulate(DateTime::<Utc>::from(UNIX_EPOCH), config.seed);
+        let rng = StdRng::seed_from_u64(config.seed);
+        let (network_tx, network_rx) = channel();
+
+        Simulation {
+            config,
+            nodes: Vec::new(),
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            network_tx,
+            network_rx,
+            rng,
+            partition: HashSet::new(),
+            trace: Vec::new(),
+            _clock: clock,
+        }
+    }
+
+    ///
+    /// Starts a new node, returns the address it is reachable at.
+    pub fn add_node(&mut self) -> Result<SocketAddr> {
+        let cluster = self.config.cluster.clone();
+        self.start_node(runtime::random_uuid(), cluster)
+    }
+
+    ///
+    /// Starts a new node with the given host key, like a member restarted at another
+    /// address or an impostor would.
+    pub fn add_node_as(&mut self, host_key: Uuid) -> Result<SocketAddr> {
+        let cluster = self.config.cluster.clone();
+        self.start_node(host_key, cluster)
+    }
+
+    ///
+    /// Starts a new node with its own configuration instead of the simulation's one,
+    /// like a node with another cluster key would.
+    pub fn add_node_with(&mut self, cluster: ClusterConfig) -> Result<SocketAddr> {
+        self.start_node(runtime::random_uuid(), cluster)
+    }
+
+    fn start_node(&mut self, host_key: Uuid, cluster: ClusterConfig) -> Result<SocketAddr> {
+        let next_port = u16::try_from(self.nodes.len())
+            .ok()
+            .and_then(|index| FIRST_PORT.checked_add(index));
+        let port = match next_port {
+            Some(port) => port,
+            None => {
+                bail!(ArtilleryError::Config, "too many simulated nodes");
+            }
+        };
+        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+
+        let metrics = Arc::new(Metrics::default());
+        let config = ClusterConfig {
+            listen_addr: addr,
+            advertise_addr: None,
+            seed_resolve_interval: Duration::zero(),
+            snapshot_path: None,
+            metrics: metrics.clone(),
+            metrics_addr: None,
+            ..cluster
+        };
+
+        let (event_tx, events) = event_channel();
+        let (request_tx, requests) = channel();
+        let transport = VirtualTransport {
+            addr,
+            network: self.network_tx.clone(),
+        };
+        // Nothing polls, the simulation steps through the epidemic instead.
+        let (_, state) = ArtilleryEpidemic::with_transport(
+            host_key,
+            config,
+            event_tx,
+            request_tx.clone(),
+            Box::new(transport),
+        )?;
+
+        self.nodes.push(Node {
+            addr,
+            host_key,
+            state,
+            request_tx,
+            requests,
+            events,
+            metrics,
+            answers: Vec::new(),
+            up: true,
+        });
+
+        Ok(addr)
+    }
+
+    ///
+    /// Lets the node join the cluster through the seed.
+    pub fn join(&self, node: SocketAddr, seed: SocketAddr) -> Result<()> {
+        self.node(node)?
+            .request_tx
+            .send(ArtilleryClusterRequest::AddSeed(seed))?;
+
+        Ok(())
+    }
+
+    ///
+    /// Stops the node without a goodbye, it neither sends nor receives anymore.
+    pub fn kill(&mut self, node: SocketAddr) -> Result<()> {
+        self.node_mut(node)?.up = false;
+
+        Ok(())
+    }
+
+    ///
+    /// Makes the node leave the cluster gracefully. Its status arrives once enough
+    /// members acked the leave, or the leave timeout passed.
+    pub fn leave(&self, node: SocketAddr) -> Result<EventReceiver<LeaveStatus>> {
+        let (tx, rx) = event_channel();
+        self.node(node)?
+            .request_tx
+            .send(ArtilleryClusterRequest::Leave(tx))?;
+
+        Ok(rx)
+    }
+
+    ///
+    /// Replaces the metadata of the node. The outcome arrives once the node stepped.
+    pub fn set_metadata(
+        &self,
+        node: SocketAddr,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<Receiver<Result<()>>> {
+        let (tx, rx) = channel();
+        self.node(node)?
+            .request_tx
+            .send(ArtilleryClusterRequest::SetMetadata(metadata, tx))?;
+
+        Ok(rx)
+    }
+
+    ///
+    /// Cuts the given nodes off from the rest, packets only flow within each side.
+    pub fn partition(&mut self, nodes: &[SocketAddr]) {
+        self.partition = nodes.iter().cloned().collect();
+    }
+
+    ///
+    /// Sends a raw packet to the node from any address, like stray traffic would.
+    pub fn inject(&self, from: SocketAddr, to: SocketAddr, packet: &[u8]) {
+        let _ = self.network_tx.send(Datagram {
+            from,
+            to,
+            bytes: packet.to_vec(),
+            transfer: Transfer::Packet,
+        });
+    }
+
+    pub fn heal(&mut self) {
+        self.partition.clear();
+    }
+
+    pub fn set_network(&mut self, conditions: NetworkConditions) {
+        self.config.network = conditions;
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        runtime::now()
+    }
+
+    pub fn host_key(&self, node: SocketAddr) -> Result<Uuid> {
+        Ok(self.node(node)?.host_key)
+    }
+
+    ///
+    /// Members as the node sees them.
+    pub fn members(&self, node: SocketAddr) -> Result<Vec<ArtilleryMember>> {
+        Ok(self.node(node)?.state.member_list())
+    }
+
+    pub fn metrics(&self, node: SocketAddr) -> Result<MetricsSnapshot> {
+        Ok(self.node(node)?.metrics.snapshot())
+    }
+
+    ///
+    /// State of `subject` as `observer` sees it.
+    pub fn state_of(
+        &self,
+        observer: SocketAddr,
+        subject: SocketAddr,
+    ) -> Option<ArtilleryMemberState> {
+        self.members(observer)
+            .ok()?
+            .iter()
+            .find(|m| m.remote_host() == Some(subject))
+            .map(ArtilleryMember::state)
+    }
+
+    ///
+    /// Events of every node so far, in the order they were observed.
+    pub fn trace(&self) -> &[Observation] {
+        &self.trace
+    }
+
+    ///
+    /// Whether every node in the cluster sees `subject` in the given state.
+    pub fn agree_on(&self, subject: SocketAddr, state: ArtilleryMemberState) -> bool {
+        self.cluster_nodes()
+            .filter(|node| node.addr != subject)
+            .all(|node| self.state_of(node.addr, subject) == Some(state))
+    }
+
+    ///
+    /// Whether every node in the cluster sees every other one alive.
+    pub fn converged(&self) -> bool {
+        self.cluster_nodes()
+            .all(|node| self.agree_on(node.addr, ArtilleryMemberState::Alive))
+    }
+
+    ///
+    /// Advances the clock by one step, delivering the packets due and letting every
+    /// running node do its work.
+    pub fn step(&mut self) -> Result<()> {
+        runtime::advance(self.config.step);
+        let now = runtime::now();
+
+        while let Some(&key) = self.in_flight.keys().next() {
+            if key.0 > now {
+                break;
+            }
+
+            if let Some(datagram) = self.in_flight.remove(&key) {
+                self.deliver(datagram)?;
+            }
+        }
+
+        for node in self.nodes.iter_mut().filter(|node| node.up) {
+            node.state.tick()?;
+            node.state.run_timers();
+            node.state.process_requests(&node.requests);
+            node.up = node.state.is_running();
+
+            for (opener, answer, on_answer) in node.answers.drain(..) {
+                match answer.try_recv() {
+                    Ok(bytes) => {
+                        let _ = self.network_tx.send(Datagram {
+                            from: node.addr,
+                            to: opener,
+                            bytes,
+                            transfer: Transfer::StreamAnswer(on_answer),
+                        });
+                    }
+                    Err(_) => on_answer(Err(io::ErrorKind::ConnectionReset.into())),
+                }
+            }
+
+            for (_, event) in node.events.try_iter() {
+                self.trace.push(Observation {
+                    at: now,
+                    node: node.addr,
+                    event,
+                });
+            }
+        }
+
+        self.transmit(now);
+
+        Ok(())
+    }
+
+    ///
+    /// Steps through the given simulated time.
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let until = runtime::now() + duration;
+        while runtime::now() < until {
+            self.step()?;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Steps until the condition holds, for the given simulated time at most. Returns
+    /// whether the condition was met.
+    pub fn run_until<F>(&mut self, limit: Duration, mut condition: F) -> Result<bool>
+    where
+        F: FnMut(&Simulation) -> bool,
+    {
+        let until = runtime::now() + limit;
+        while !condition(self) {
+            if runtime::now() >= until {
+                return Ok(false);
+            }
+            self.step()?;
+        }
+
+        Ok(true)
+    }
+
+    fn deliver(&mut self, datagram: Datagram) -> Result<()> {
+        let reachable =
+            self.partition.contains(&datagram.from) == self.partition.contains(&datagram.to);
+        let target = network::canonical(datagram.to);
+        let recipient = self
+            .nodes
+            .iter_mut()
+            .find(|node| reachable && node.up && node.addr == target);
+
+        match (recipient, datagram.transfer) {
+            (Some(node), Transfer::Packet) => {
+                node.state.receive_packet(datagram.from, &datagram.bytes)?
+            }
+            (Some(node), Transfer::StreamRequest(on_answer)) => {
+                let (answer_tx, answer) = channel();
+                node.answers.push((datagram.from, answer, on_answer));
+                node.request_tx.send(ArtilleryClusterRequest::Stream(
+                    datagram.from,
+                    datagram.bytes,
+                    answer_tx,
+                ))?;
+            }
+            (Some(_), Transfer::StreamAnswer(on_answer)) => on_answer(Ok(datagram.bytes)),
+            (None, Transfer::Packet) => {}
+            (None, Transfer::StreamRequest(on_answer))
+            | (None, Transfer::StreamAnswer(on_answer)) => {
+                on_answer(Err(io::ErrorKind::ConnectionRefused.into()))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Puts the packets sent during the step on the way, as the network conditions say.
+    // Streams are neither lost nor duplicated, only delayed.
+    fn transmit(&mut self, now: DateTime<Utc>) {
+        let conditions = self.config.network.clone();
+        let jitter = conditions.jitter.num_microseconds().unwrap_or(0).max(0);
+
+        while let Ok(datagram) = self.network_rx.try_recv() {
+            let packet = match datagram.transfer {
+                Transfer::Packet => true,
+                Transfer::StreamRequest(_) | Transfer::StreamAnswer(_) => false,
+            };
+            if packet && self.rng.gen::<f64>() < conditions.loss {
+                continue;
+            }
+
+            if packet && self.rng.gen::<f64>() < conditions.duplication {
+                let duplicate = Datagram {
+                    from: datagram.from,
+                    to: datagram.to,
+                    bytes: datagram.bytes.clone(),
+                    transfer: Transfer::Packet,
+                };
+                self.put_in_flight(now, duplicate, jitter);
+            }
+            self.put_in_flight(now, datagram, jitter);
+        }
+    }
+
+    fn put_in_flight(&mut self, now: DateTime<Utc>, datagram: Datagram, jitter: i64) {
+        let delay =
+            self.config.network.latency + Duration::microseconds(self.rng.gen_range(0, jitter + 1));
+        self.sent += 1;
+        self.in_flight.insert((now + delay, self.sent), datagram);
+    }
+
+    // Running nodes which did not leave.
+    fn cluster_nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter().filter(|node| {
+            node.up
+                && node
+                    .state
+                    .member_list()
+                    .iter()
+                    .any(|m| m.is_current() && m.state() != ArtilleryMemberState::Left)
+        })
+    }
+
+    fn node(&self, addr: SocketAddr) -> Result<&Node> {
+        match self.nodes.iter().find(|node| node.addr == addr) {
+            Some(node) => Ok(node),
+            None => {
+                bail!(ArtilleryError::Config, "no simulated node at {}", addr);
+            }
+        }
+    }
+
+    fn node_mut(&mut self, addr: SocketAddr) -> Result<&mut Node> {
+        match self.nodes.iter_mut().find(|node| node.addr == addr) {
+            Some(node) => Ok(node),
+            None => {
+                bail!(ArtilleryError::Config, "no simulated node at {}", addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NetworkConditions, Simulation, SimulationConfig};
+    use crate::epidemic::cluster_config::ClusterConfig;
+    use crate::epidemic::member::ArtilleryMemberState;
+    use crate::epidemic::state::{ArtilleryMemberEvent, LeaveStatus};
+    use crate::errors::*;
+    use chrono::Duration;
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+
+    fn config(seed: u64) -> SimulationConfig {
+        SimulationConfig {
+            seed,
+            network: NetworkConditions {
+                jitter: Duration::milliseconds(5),
+                ..NetworkConditions::default()
+            },
+            cluster: ClusterConfig {
+                ping_interval: Duration::milliseconds(200),
+                ping_timeout: Duration::milliseconds(100),
+                ..ClusterConfig::default()
+            },
+            ..SimulationConfig::default()
+        }
+    }
+
+    fn lossy() -> NetworkConditions {
+        NetworkConditions {
+            loss: 0.05,
+            duplication: 0.02,
+            latency: Duration::milliseconds(5),
+            jitter: Duration::milliseconds(20),
+        }
+    }
+
+    fn start(simulation: &mut Simulation, size: usize) -> Result<Vec<SocketAddr>> {
+        let nodes = (0..size)
+            .map(|_| simulation.add_node())
+            .collect::<Result<Vec<_>>>()?;
+        for node in &nodes[1..] {
+            simulation.join(*node, nodes[0])?;
+        }
+
+        Ok(nodes)
+    }
+
+    #[test]
+    fn test_cluster_converges_and_detects_failures() -> Result<()> {
+        let mut simulation = Simulation::new(config(1));
+        let nodes = start(&mut simulation, 24)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        simulation.set_network(lossy());
+        simulation.kill(nodes[5])?;
+        let detected = simulation.run_until(Duration::seconds(60), |s| {
+            s.agree_on(nodes[5], ArtilleryMemberState::Down)
+        })?;
+        assert!(detected);
+
+        simulation.leave(nodes[9])?;
+        let left = simulation.run_until(Duration::seconds(30), |s| {
+            s.agree_on(nodes[9], ArtilleryMemberState::Left)
+        })?;
+        assert!(left);
+
+        simulation.set_network(config(1).network);
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partitioned_sides_heal() -> Result<()> {
+        let mut simulation = Simulation::new(config(2));
+        let nodes = start(&mut simulation, 12)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        simulation.partition(&nodes[..4]);
+        let split = simulation.run_until(Duration::seconds(60), |s| {
+            let down = Some(ArtilleryMemberState::Down);
+            s.state_of(nodes[0], nodes[8]) == down && s.state_of(nodes[8], nodes[0]) == down
+        })?;
+        assert!(split);
+        assert_eq!(
+            simulation.state_of(nodes[0], nodes[3]),
+            Some(ArtilleryMemberState::Alive)
+        );
+
+        simulation.heal();
+        simulation.join(nodes[0], nodes[8])?;
+        assert!(simulation.run_until(Duration::seconds(60), Simulation::converged)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_garbage_quarantines_its_source() -> Result<()> {
+        let mut simulation = Simulation::new(config(5));
+        let nodes = start(&mut simulation, 4)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        // Right version byte, garbage after it.
+        let stray: SocketAddr = "10.9.9.9:27845".parse().unwrap();
+        for _ in 0..25 {
+            simulation.inject(stray, nodes[0], &[1, 0xff, 0xff, 0xff]);
+        }
+        simulation.run_for(Duration::seconds(1))?;
+
+        let metrics = simulation.metrics(nodes[0])?;
+        let threshold = u64::from(ClusterConfig::default().quarantine.threshold);
+        assert_eq!(metrics.decode_failures, threshold);
+        assert_eq!(metrics.quarantined_packets, 25 - threshold);
+        assert_eq!(metrics.quarantined_sources, 1);
+        assert!(simulation.converged());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_peers_on_other_versions_are_not_quarantined() -> Result<()> {
+        let mut simulation = Simulation::new(config(6));
+        let nodes = start(&mut simulation, 2)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        // Peer already speaking a protocol version from the future.
+        let upgraded: SocketAddr = "10.9.9.10:27845".parse().unwrap();
+        for _ in 0..25 {
+            simulation.inject(upgraded, nodes[0], &[0x7f, 0x00]);
+        }
+        simulation.run_for(Duration::seconds(1))?;
+
+        let metrics = simulation.metrics(nodes[0])?;
+        assert_eq!(metrics.decode_failures, 25);
+        assert_eq!(metrics.quarantined_packets, 0);
+        assert_eq!(metrics.quarantined_sources, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_peers_with_another_key_are_quarantined() -> Result<()> {
+        let mut encrypted = config(9);
+        encrypted.cluster.gossip_encryption = true;
+        let mut simulation = Simulation::new(encrypted.clone());
+        let nodes = start(&mut simulation, 3)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        let stranger = simulation.add_node_with(ClusterConfig {
+            cluster_key: b"another cluster".to_vec(),
+            ..encrypted.cluster
+        })?;
+        simulation.join(stranger, nodes[0])?;
+        let quarantined = simulation.run_until(Duration::seconds(60), |s| {
+            s.metrics(nodes[0])
+                .map(|metrics| metrics.quarantined_packets > 0)
+                .unwrap_or(false)
+        })?;
+        assert!(quarantined);
+
+        let metrics = simulation.metrics(nodes[0])?;
+        let threshold = u64::from(ClusterConfig::default().quarantine.threshold);
+        assert_eq!(metrics.decode_failures, threshold);
+        assert_eq!(metrics.quarantined_sources, 1);
+        assert_eq!(simulation.state_of(nodes[0], stranger), None);
+
+        // The stranger sees nobody, the cluster itself is unaffected.
+        simulation.kill(stranger)?;
+        assert!(simulation.converged());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata_is_gossiped_as_an_update() -> Result<()> {
+        let mut simulation = Simulation::new(config(10));
+        let nodes = start(&mut simulation, 4)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        let mut metadata = BTreeMap::new();
+        metadata.insert("role".to_string(), "db".to_string());
+        let outcome = simulation.set_metadata(nodes[1], metadata.clone())?;
+
+        let spread = simulation.run_until(Duration::seconds(10), |s| {
+            nodes.iter().filter(|node| **node != nodes[1]).all(|node| {
+                s.members(*node)
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|m| m.remote_host() == Some(nodes[1]) && m.metadata() == &metadata)
+            })
+        })?;
+        assert!(spread);
+        assert!(matches!(outcome.try_recv(), Ok(Ok(()))));
+
+        for node in nodes.iter().filter(|node| **node != nodes[1]) {
+            let updated = simulation.trace().iter().any(|o| match &o.event {
+                ArtilleryMemberEvent::Updated(member) => {
+                    o.node == *node
+                        && member.remote_host() == Some(nodes[1])
+                        && member.metadata() == &metadata
+                }
+                _ => false,
+            });
+            assert!(updated, "{} saw no update", node);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leave_is_confirmed_by_the_members() -> Result<()> {
+        let mut simulation = Simulation::new(config(11));
+        let nodes = start(&mut simulation, 5)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        let status = simulation.leave(nodes[4])?;
+        let mut outcome = None;
+        simulation.run_until(Duration::seconds(10), |_| {
+            outcome = outcome.or_else(|| status.try_recv().ok());
+            outcome.is_some()
+        })?;
+        assert_eq!(outcome, Some(LeaveStatus::Confirmed));
+        assert!(simulation.run_until(Duration::seconds(10), |s| {
+            s.agree_on(nodes[4], ArtilleryMemberState::Left)
+        })?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unacked_leave_times_out() -> Result<()> {
+        let mut simulation = Simulation::new(config(12));
+        let nodes = start(&mut simulation, 5)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        // Nobody hears the leave.
+        simulation.partition(&nodes[4..]);
+        let started = simulation.now();
+        let status = simulation.leave(nodes[4])?;
+        let mut outcome = None;
+        simulation.run_until(Duration::seconds(10), |_| {
+            outcome = outcome.or_else(|| status.try_recv().ok());
+            outcome.is_some()
+        })?;
+        assert_eq!(outcome, Some(LeaveStatus::TimedOut));
+        assert!(simulation.now() - started >= ClusterConfig::default().leave_timeout);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_seed_gives_the_same_run() -> Result<()> {
+        let run = |seed| -> Result<String> {
+            let mut simulation = Simulation::new(config(seed));
+            simulation.set_network(lossy());
+            let nodes = start(&mut simulation, 8)?;
+            simulation.run_for(Duration::seconds(5))?;
+            simulation.kill(nodes[3])?;
+            simulation.run_for(Duration::seconds(20))?;
+
+            Ok(format!("{:?}", simulation.trace()))
+        };
+
+        assert_eq!(run(3)?, run(3)?);
+        assert_ne!(run(3)?, run(4)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restarted_member_moves_to_its_new_address() -> Result<()> {
+        let mut simulation = Simulation::new(config(7));
+        let nodes = start(&mut simulation, 3)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        let host_key = simulation.host_key(nodes[2])?;
+        simulation.kill(nodes[2])?;
+        let moved = simulation.add_node_as(host_key)?;
+        simulation.join(moved, nodes[0])?;
+
+        let changed = simulation.run_until(Duration::seconds(5), |s| {
+            s.trace().iter().any(|o| match &o.event {
+                ArtilleryMemberEvent::AddressChanged(old_addr, member) => {
+                    o.node == nodes[0]
+                        && *old_addr == nodes[2]
+                        && member.remote_host() == Some(moved)
+                }
+                _ => false,
+            })
+        })?;
+        assert!(changed);
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_impostor_is_reported_and_not_merged() -> Result<()> {
+        let mut simulation = Simulation::new(config(8));
+        let nodes = start(&mut simulation, 3)?;
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        let impostor = simulation.add_node_as(simulation.host_key(nodes[2])?)?;
+        simulation.join(impostor, nodes[0])?;
+        simulation.run_for(Duration::seconds(5))?;
+
+        let conflicts: Vec<_> = simulation
+            .trace()
+            .iter()
+            .filter(|o| match &o.event {
+                ArtilleryMemberEvent::Conflict(member, claimant) => {
+                    o.node == nodes[0]
+                        && member.remote_host() == Some(nodes[2])
+                        && *claimant == impostor
+                }
+                _ => false,
+            })
+            .collect();
+        assert_eq!(conflicts.len(), 1);
+
+        assert_eq!(
+            simulation.state_of(nodes[0], nodes[2]),
+            Some(ArtilleryMemberState::Alive)
+        );
+        assert_eq!(simulation.state_of(nodes[0], impostor), None);
+        assert_eq!(simulation.state_of(impostor, nodes[0]), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::errors::*;
+use serde::*;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+///
+/// Member list of a node as it is persisted, for rejoining quickly after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub host_key: Uuid,
+    pub incarnation_number: u64,
+    pub peers: Vec<SocketAddr>,
+}
+
+impl Snapshot {
+    ///
+    /// Reads the snapshot at `path`, a missing file gives `None`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Snapshot>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    ///
+    /// Writes the snapshot next to `path` first and moves it over, so that a crash
+    /// never leaves a torn snapshot behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let staging = staging_path(path.as_ref());
+
+        let mut file = File::create(&staging)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+
+        fs::rename(&staging, path.as_ref())?;
+
+        Ok(())
+    }
+}
+
+fn staging_path(path: &Path) -> PathBuf {
+    let mut staging = OsString::from(path.as_os_str());
+    staging.push(".tmp");
+
+    PathBuf::from(staging)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Snapshot;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let path = std::env::temp_dir().join(format!("artillery-{}.json", Uuid::new_v4()));
+        assert_eq!(Snapshot::load(&path).unwrap(), None);
+
+        let snapshot = Snapshot {
+            host_key: Uuid::new_v4(),
+            incarnation_number: 7,
+            peers: vec!["10.0.0.2:27845".parse().unwrap()],
+        };
+        snapshot.save(&path).unwrap();
+        assert_eq!(Snapshot::load(&path).unwrap(), Some(snapshot));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
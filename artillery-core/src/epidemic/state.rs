@@ -1,24 +1,44 @@
-use super::cluster_config::ClusterConfig;
+use super::awareness::Awareness;
+use super::broadcast::{ArtilleryBroadcast, BroadcastLog, BroadcastQueue};
+use super::cluster_config::{ClusterConfig, ClusterConfigUpdate};
+use super::coordinate::{Coordinate, CoordinateClient};
+use super::delegate::{
+    EventDelegate, EventDispatcher, EventKind, SubscriptionConfig, SubscriptionId,
+};
+use super::dissemination::{self, StateChangeQueue};
+use super::keyring::Keyring;
 use super::membership::ArtilleryMemberList;
+use super::network;
+use super::payload::{ArtilleryPayload, PayloadEvent, PayloadRouter};
+use super::quarantine::Quarantine;
+use super::query::{ArtilleryQuery, IncomingQuery, QueryResponse};
+use super::runtime;
+use super::seeds::{self, SeedList};
+use super::snapshot::Snapshot;
+use super::stream::{self, PushPullState, StreamMessage, StreamPayload};
+use super::suspicion::SuspicionBounds;
+use super::transport::{StreamAnswer, Transport, UdpTransport};
+use super::wire;
 use crate::epidemic::member::{ArtilleryMember, ArtilleryMemberState, ArtilleryStateChange};
 use crate::errors::*;
+use crate::events::EventSender;
+use crate::metrics::{self, Metrics};
 use chrono::{DateTime, Utc};
 use cuneiform_fields::prelude::*;
-use mio::net::UdpSocket;
-use mio::{Events, Interest, Poll, Token};
+use mio::{Events, Poll, Token, Waker};
 use serde::*;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
 use std::time::Duration;
 use uuid::Uuid;
 
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
-use std::time::Instant;
 
 use kaos::flunk;
 
@@ -27,34 +47,51 @@ use crate::constants::*;
 pub type ArtilleryClusterEvent = (Vec<ArtilleryMember>, ArtilleryMemberEvent);
 pub type WaitList = HashMap<SocketAddr, Vec<SocketAddr>>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ArtilleryMemberEvent {
     Joined(ArtilleryMember),
     WentUp(ArtilleryMember),
     SuspectedDown(ArtilleryMember),
     WentDown(ArtilleryMember),
     Left(ArtilleryMember),
-    Payload(ArtilleryMember, String),
+    Updated(ArtilleryMember),
+    Reaped(ArtilleryMember),
+    /// Member was found at a new address, the old one is given along
+    AddressChanged(SocketAddr, ArtilleryMember),
+    /// Another live node at the given address claims the host key of this member
+    Conflict(ArtilleryMember, SocketAddr),
+    /// Payload on a topic without subscribers
+    Payload(ArtilleryMember, ArtilleryPayload),
+    Broadcast(ArtilleryBroadcast),
+    Query(IncomingQuery),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ArtilleryMessage {
     sender: Uuid,
+    // Address the sender advertises, its packets might come from elsewhere.
+    sender_addr: Option<SocketAddr>,
     cluster_key: Vec<u8>,
     request: Request,
     state_changes: Vec<ArtilleryStateChange>,
+    broadcasts: Vec<ArtilleryBroadcast>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 struct EncSocketAddr(SocketAddr);
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 enum Request {
     Heartbeat,
-    Ack,
+    Ack(Coordinate),
     Ping(EncSocketAddr),
     AckHost(ArtilleryMember),
-    Payload(Uuid, String),
+    Payload(ArtilleryPayload),
+    ReliablePayload(u64, ArtilleryPayload),
+    PayloadAck(u64),
+    Query(ArtilleryQuery),
+    QueryAck(u64),
+    QueryAnswer(u64, Vec<u8>),
 }
 
 #[derive(Debug, Clone)]
@@ -63,30 +100,149 @@ pub struct TargetedRequest {
     target: SocketAddr,
 }
 
+#[derive(Clone)]
+pub enum KeyringRequest {
+    Install(Vec<u8>),
+    Use(Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaveStatus {
+    /// Enough members acked our `Left` state
+    Confirmed,
+    /// Leave timeout passed before enough members acked it
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Target member acked the payload
+    Delivered,
+    /// No ack arrived before the payload timeout
+    TimedOut,
+    /// Target isn't a known remote member
+    UnknownPeer,
+}
+
+struct PendingPayload {
+    target: Uuid,
+    payload: ArtilleryPayload,
+    deadline: DateTime<Utc>,
+    next_attempt: DateTime<Utc>,
+    backoff: chrono::Duration,
+    waiter: EventSender<DeliveryStatus>,
+}
+
+struct PendingQuery {
+    deadline: DateTime<Utc>,
+    responses: EventSender<QueryResponse>,
+}
+
+struct LeaveProgress {
+    deadline: DateTime<Utc>,
+    confirmations: usize,
+    acked_by: HashSet<SocketAddr>,
+    waiters: Vec<EventSender<LeaveStatus>>,
+}
+
 #[derive(Clone)]
 pub enum ArtilleryClusterRequest {
     AddSeed(SocketAddr),
+    AddSeedHost(String),
+    SeedsResolved(String, Vec<SocketAddr>),
     Respond(SocketAddr, ArtilleryMessage),
     React(TargetedRequest),
     LeaveCluster,
+    Leave(EventSender<LeaveStatus>),
+    Keyring(KeyringRequest, Sender<Result<()>>),
+    SetMetadata(BTreeMap<String, String>, Sender<Result<()>>),
+    Stream(SocketAddr, Vec<u8>, Sender<Vec<u8>>),
+    SyncWith(SocketAddr, Option<Sender<Result<()>>>),
+    StreamResponse(SocketAddr, Vec<u8>, Option<Sender<Result<()>>>),
     Exit(Sender<()>),
-    Payload(Uuid, String),
+    Payload(Uuid, ArtilleryPayload),
+    DeliverPayload(Uuid, ArtilleryPayload, EventSender<DeliveryStatus>),
+    Subscribe(String, EventSender<PayloadEvent>),
+    Broadcast(String, Vec<u8>, Sender<Result<()>>),
+    Query(ArtilleryQuery, EventSender<QueryResponse>),
+    Coordinates(Sender<HashMap<Uuid, Coordinate>>),
+    Reconfigure(ClusterConfigUpdate, Sender<Result<()>>),
+    AnswerQuery(Option<SocketAddr>, u64, Vec<u8>),
+    SubscribeEvents(
+        EventSender<ArtilleryClusterEvent>,
+        SubscriptionConfig,
+        Sender<SubscriptionId>,
+    ),
+    RegisterDelegate(
+        Arc<dyn EventDelegate>,
+        Vec<EventKind>,
+        Sender<SubscriptionId>,
+    ),
+    UnsubscribeEvents(SubscriptionId, Sender<bool>),
 }
 
-const UDP_SERVER: Token = Token(0);
+// Probe awaiting an ack, with its timeout, target, piggybacked state changes and send
+// time.
+type PendingResponse = (
+    DateTime<Utc>,
+    SocketAddr,
+    Vec<ArtilleryStateChange>,
+    DateTime<Utc>,
+);
+
+const TRANSPORT: Token = Token(0);
+const STREAM_WAKER: Token = Token(1);
+
+// Messages held back per member while its address is checked, the rest are dropped.
+const MAX_HELD_MESSAGES: usize = 32;
 
 pub struct ArtilleryEpidemic {
     host_key: Uuid,
     config: ClusterConfig,
     members: ArtilleryMemberList,
+    seeds: SeedList,
     seed_queue: Vec<SocketAddr>,
-    pending_responses: Vec<(DateTime<Utc>, SocketAddr, Vec<ArtilleryStateChange>)>,
-    state_changes: Vec<ArtilleryStateChange>,
+    pending_responses: Vec<PendingResponse>,
+    state_changes: StateChangeQueue,
+    broadcasts: BroadcastQueue,
+    broadcast_log: BroadcastLog,
     wait_list: WaitList,
-    server_socket: UdpSocket,
+    peer_versions: HashMap<SocketAddr, u8>,
+    quarantine: Quarantine,
+    keyring: Option<Keyring>,
+    transport: Box<dyn Transport>,
     request_tx: ArchPadding<Sender<ArtilleryClusterRequest>>,
-    event_tx: ArchPadding<Sender<ArtilleryClusterEvent>>,
-    running: AtomicBool,
+    events: EventDispatcher,
+    awareness: Awareness,
+    metrics: Arc<Metrics>,
+    // Address the metrics exporter listens at, woken up on exit.
+    metrics_listen_addr: Option<SocketAddr>,
+    leaving: Option<LeaveProgress>,
+    // Members heard from at a new address, mapped to their (old, new) addresses
+    // while the old one is probed.
+    address_checks: HashMap<Uuid, (SocketAddr, SocketAddr)>,
+    // Messages of those members along with their source, answered once the check
+    // settles.
+    held_messages: HashMap<Uuid, Vec<(SocketAddr, ArtilleryMessage)>>,
+    conflicts: HashMap<SocketAddr, Uuid>,
+    payload_seq: u64,
+    outgoing_payloads: HashMap<u64, PendingPayload>,
+    delivered_payloads: HashMap<(Uuid, u64), DateTime<Utc>>,
+    payload_router: PayloadRouter,
+    query_seq: u64,
+    queries: HashMap<u64, PendingQuery>,
+    coordinates: CoordinateClient,
+    member_coordinates: HashMap<Uuid, Coordinate>,
+    // Own incarnation as of the last snapshot written.
+    snapshot_incarnation: Option<u64>,
+    // When the periodic work of the event loop last ran.
+    last_probe: DateTime<Utc>,
+    last_push_pull: DateTime<Utc>,
+    last_seed_resolution: DateTime<Utc>,
+    last_snapshot: DateTime<Utc>,
+    stream_waker: Arc<Waker>,
+    running: Arc<AtomicBool>,
 }
 
 pub type ClusterReactor = (Poll, ArtilleryEpidemic);
@@ -95,33 +251,142 @@ impl ArtilleryEpidemic {
     pub fn new(
         host_key: Uuid,
         config: ClusterConfig,
-        event_tx: Sender<ArtilleryClusterEvent>,
+        event_tx: EventSender<ArtilleryClusterEvent>,
+        internal_tx: Sender<ArtilleryClusterRequest>,
+    ) -> Result<ClusterReactor> {
+        let transport = UdpTransport::bind(config.listen_addr)?;
+
+        Self::with_transport(host_key, config, event_tx, internal_tx, Box::new(transport))
+    }
+
+    ///
+    /// Epidemic over the given transport instead of a UDP socket at the listen address.
+    pub fn with_transport(
+        host_key: Uuid,
+        config: ClusterConfig,
+        event_tx: EventSender<ArtilleryClusterEvent>,
         internal_tx: Sender<ArtilleryClusterRequest>,
+        mut transport: Box<dyn Transport>,
     ) -> Result<ClusterReactor> {
+        validate_config(&config)?;
+
         let poll: Poll = Poll::new()?;
+        let stream_waker = Arc::new(Waker::new(poll.registry(), STREAM_WAKER)?);
+        transport.register(poll.registry(), TRANSPORT, stream_waker.clone())?;
+
+        let mut state = Self::assemble(
+            host_key,
+            config,
+            event_tx,
+            internal_tx,
+            transport,
+            stream_waker,
+        )?;
+
+        // Push/pull streams share the port of the gossip socket.
+        if let Some(stream_addr) = state.transport.stream_listen_addr() {
+            stream::spawn_listener(
+                network::bind_tcp(stream_addr)?,
+                std_duration(state.config.stream_timeout)?,
+                state.config.max_incoming_streams,
+                (*state.request_tx).clone(),
+                state.stream_waker.clone(),
+                state.running.clone(),
+            );
+        }
+
+        if let Some(metrics_addr) = state.config.metrics_addr {
+            let metrics_listener = TcpListener::bind(metrics_addr)?;
+            state.metrics_listen_addr = Some(metrics_listener.local_addr()?);
+            metrics::spawn_exporter(
+                metrics_listener,
+                state.metrics.clone(),
+                std_duration(state.config.stream_timeout)?,
+                state.running.clone(),
+            );
+        }
 
-        let interests = Interest::READABLE.add(Interest::WRITABLE);
-        let mut server_socket = UdpSocket::bind(config.listen_addr)?;
-        poll.registry()
-            .register(&mut server_socket, UDP_SERVER, interests)?;
+        Ok((poll, state))
+    }
+
+    fn assemble(
+        host_key: Uuid,
+        config: ClusterConfig,
+        event_tx: EventSender<ArtilleryClusterEvent>,
+        internal_tx: Sender<ArtilleryClusterRequest>,
+        transport: Box<dyn Transport>,
+        stream_waker: Arc<Waker>,
+    ) -> Result<ArtilleryEpidemic> {
+        let previous_run = load_snapshot(&config);
+        let metrics = config.metrics.clone();
+        let mut events = EventDispatcher::new();
+        events.subscribe(event_tx, &config.events);
+        let mut me = ArtilleryMember::current(host_key);
+        me.set_metadata(config.metadata.clone());
+        if let Some(snapshot) = &previous_run {
+            if snapshot.host_key == host_key {
+                // Gossip about our previous run must not override the new Alive state.
+                me.resume_after(snapshot.incarnation_number);
+            }
+            for peer in &snapshot.peers {
+                internal_tx.send(ArtilleryClusterRequest::AddSeed(*peer))?;
+            }
+        }
+        let awareness = Awareness::new(config.awareness_max_multiplier);
+        let coordinates = CoordinateClient::new(config.coordinates.clone());
+        let mut state_changes = StateChangeQueue::new();
+        state_changes.enqueue(me.clone());
+        let keyring = if config.gossip_encryption {
+            Some(Keyring::new(
+                &config.cluster_key,
+                &config.secondary_cluster_keys,
+            ))
+        } else {
+            None
+        };
 
-        let me = ArtilleryMember::current(host_key);
+        let now = runtime::now();
 
-        let state = ArtilleryEpidemic {
+        Ok(ArtilleryEpidemic {
             host_key,
             config,
             members: ArtilleryMemberList::new(me.clone()),
+            seeds: SeedList::new(),
             seed_queue: Vec::new(),
             pending_responses: Vec::new(),
-            state_changes: vec![ArtilleryStateChange::new(me)],
+            state_changes,
+            broadcasts: BroadcastQueue::new(),
+            broadcast_log: BroadcastLog::new(),
             wait_list: HashMap::new(),
-            server_socket,
+            peer_versions: HashMap::new(),
+            quarantine: Quarantine::new(),
+            keyring,
+            transport,
             request_tx: ArchPadding::new(internal_tx),
-            event_tx: ArchPadding::new(event_tx),
-            running: AtomicBool::new(true),
-        };
-
-        Ok((poll, state))
+            events,
+            awareness,
+            metrics,
+            metrics_listen_addr: None,
+            leaving: None,
+            address_checks: HashMap::new(),
+            held_messages: HashMap::new(),
+            conflicts: HashMap::new(),
+            payload_seq: 0,
+            outgoing_payloads: HashMap::new(),
+            delivered_payloads: HashMap::new(),
+            payload_router: PayloadRouter::new(),
+            query_seq: 0,
+            queries: HashMap::new(),
+            coordinates,
+            member_coordinates: HashMap::new(),
+            snapshot_incarnation: None,
+            last_probe: now,
+            last_push_pull: now,
+            last_seed_resolution: now,
+            last_snapshot: now,
+            stream_waker,
+            running: Arc::new(AtomicBool::new(true)),
+        })
     }
 
     pub(crate) fn event_loop(
@@ -132,109 +397,382 @@ impl ArtilleryEpidemic {
         let mut events = Events::with_capacity(1);
         let mut buf = [0_u8; CONST_PACKET_SIZE];
 
-        let mut start = Instant::now();
-        let timeout = Duration::from_millis(u64::try_from(
-            state.config.ping_interval.num_milliseconds(),
-        )?);
-
         debug!("Starting Event Loop");
         // Our event loop.
         loop {
-            let elapsed = start.elapsed();
-
-            if elapsed >= timeout {
-                state.enqueue_seed_nodes();
-                state.enqueue_random_ping();
-                start = Instant::now();
-            }
+            let remaining = state.tick()?;
 
             if !state.running.load(Ordering::SeqCst) {
                 debug!("Stopping artillery epidemic evloop");
                 break;
             }
 
-            // Poll to check if we have events waiting for us.
-            if let Some(remaining) = timeout.checked_sub(elapsed) {
-                poll.poll(&mut events, Some(remaining))?;
-            }
+            state.run_timers();
 
-            // Process our own events that are submitted to event loop
-            // Aka outbound events
-            while let Ok(msg) = receiver.try_recv() {
-                let exit_tx = state.process_internal_request(msg);
+            // Poll to check if we have events waiting for us.
+            let wait = match state.next_timer()? {
+                Some(next_timer) => next_timer.min(remaining),
+                None => remaining,
+            };
+            poll.poll(&mut events, Some(wait))?;
 
-                if let Some(exit_tx) = exit_tx {
-                    state.running.swap(false, Ordering::SeqCst);
-                    exit_tx.send(()).unwrap();
+            // Process inbound events. Transports which aren't registered with the poll
+            // wake us up through the waker, along with the queued stream requests.
+            let mut readable = false;
+            for event in events.iter() {
+                match event.token() {
+                    TRANSPORT | STREAM_WAKER => readable = true,
+                    _ => warn!("Got event for unexpected token: {:?}", event),
                 }
             }
 
-            // Process inbound events
-            for event in events.iter() {
-                if let UDP_SERVER = event.token() {
-                    loop {
-                        match state.server_socket.recv_from(&mut buf) {
-                            Ok((packet_size, source_address)) => {
-                                let message = serde_json::from_slice(&buf[..packet_size])?;
-                                state.request_tx.send(ArtilleryClusterRequest::Respond(
-                                    source_address,
-                                    message,
-                                ))?;
-                            }
-                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                                // If we get a `WouldBlock` error we know our socket
-                                // has no more packets queued, so we can return to
-                                // polling and wait for some more.
-                                break;
-                            }
-                            Err(e) => {
-                                // If it was any other kind of error, something went
-                                // wrong and we terminate with an error.
-                                bail!(
-                                    ArtilleryError::Unexpected,
-                                    format!(
-                                        "Unexpected error occured in event loop: {}",
-                                        e.to_string()
-                                    )
-                                )
-                            }
+            while readable {
+                match state.transport.recv_from(&mut buf) {
+                    Ok((packet_size, raw_source)) => {
+                        if let Err(e) = state.receive_packet(raw_source, &buf[..packet_size]) {
+                            state.metrics.receive_errors.inc();
+                            warn!("Dropping packet from {}: {}", raw_source, e);
                         }
                     }
-                } else {
-                    warn!("Got event for unexpected token: {:?}", event);
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        // If we get a `WouldBlock` error we know our transport
+                        // has no more packets queued, so we can return to
+                        // polling and wait for some more.
+                        readable = false;
+                    }
+                    Err(e) => {
+                        // Anything else concerns a single packet, like one from a
+                        // socket outside of the cluster or the `ConnectionReset`
+                        // Windows reports after sending to a dead peer.
+                        state.metrics.receive_errors.inc();
+                        warn!("Failed to receive a packet: {}", e);
+                    }
                 }
             }
+
+            // Process our own events that are submitted to event loop
+            // Aka outbound events, along with the inbound ones queued above,
+            // so that acks don't wait for the next poll and skew the RTTs.
+            state.process_requests(receiver);
         }
 
         info!("Exiting...");
         Ok(())
     }
 
-    fn process_request(&mut self, request: &TargetedRequest) {
+    ///
+    /// Runs the periodic work which is due, returns the time until the next probe.
+    pub(crate) fn tick(&mut self) -> Result<Duration> {
+        // Probe interval stretches when our local health degrades.
+        let interval = self.probe_interval()?;
+
+        if elapsed_since(self.last_probe) >= interval {
+            self.enqueue_seed_nodes();
+            self.enqueue_random_ping();
+            self.gossip_leave();
+            self.reap_members();
+            self.quarantine.expire(&self.config.quarantine);
+            self.reseed_if_isolated();
+            self.record_gauges();
+            self.last_probe = runtime::now();
+
+            if self.config.push_pull_interval > chrono::Duration::zero()
+                && elapsed_since(self.last_push_pull)
+                    >= std_duration(self.config.push_pull_interval)?
+            {
+                self.enqueue_push_pull();
+                self.last_push_pull = runtime::now();
+            }
+
+            if self.config.seed_resolve_interval > chrono::Duration::zero()
+                && elapsed_since(self.last_seed_resolution)
+                    >= std_duration(self.config.seed_resolve_interval)?
+            {
+                self.resolve_seed_hosts();
+                self.last_seed_resolution = runtime::now();
+            }
+
+            if elapsed_since(self.last_snapshot) >= std_duration(self.config.snapshot_interval)?
+                || self.incarnation_changed()
+            {
+                self.save_snapshot();
+                self.last_snapshot = runtime::now();
+            }
+        }
+
+        Ok(interval
+            .checked_sub(elapsed_since(self.last_probe))
+            .unwrap_or_default())
+    }
+
+    ///
+    /// Retries the reliable payloads and expires the queries which are due.
+    pub(crate) fn run_timers(&mut self) {
+        self.retry_payloads();
+        self.expire_queries();
+    }
+
+    ///
+    /// Decodes a packet read off the gossip socket and queues it for processing.
+    pub(crate) fn receive_packet(&mut self, raw_source: SocketAddr, packet: &[u8]) -> Result<()> {
+        // Dual-stack sockets see IPv4 peers as v4-mapped.
+        let source_address = network::canonical(raw_source);
+        self.metrics.packets_received.inc();
+        self.metrics
+            .received_packet_sizes
+            .observe(u64::try_from(packet.len())?);
+
+        if self.quarantine.is_quarantined(source_address) {
+            self.metrics.quarantined_packets.inc();
+            return Ok(());
+        }
+
+        // Stray traffic must not take the member down, bad packets only count
+        // against their source.
+        let (version, message) = match wire::decode(packet, self.keyring.as_ref()) {
+            Ok(message) => message,
+            // Peers on another protocol version are expected while rolling out
+            // upgrades, they aren't misbehaving. Rotated keys stay in the keyring, so
+            // a peer without any of them is.
+            Err(ArtilleryError::ProtocolVersion(e)) => {
+                self.metrics.decode_failures.inc();
+                warn!("Rejecting packet from {}: {}", source_address, e);
+                return Ok(());
+            }
+            Err(e) => {
+                self.metrics.decode_failures.inc();
+                self.record_bad_packet(source_address, &e.to_string());
+                return Ok(());
+            }
+        };
+
+        self.peer_versions.insert(source_address, version);
+        self.request_tx
+            .send(ArtilleryClusterRequest::Respond(source_address, message))?;
+
+        Ok(())
+    }
+
+    ///
+    /// Processes the queued requests, stopping the epidemic on `Exit`.
+    pub(crate) fn process_requests(&mut self, receiver: &Receiver<ArtilleryClusterRequest>) {
+        while let Ok(msg) = receiver.try_recv() {
+            let exit_tx = self.process_internal_request(msg);
+
+            if let Some(exit_tx) = exit_tx {
+                self.save_snapshot();
+                self.running.swap(false, Ordering::SeqCst);
+                self.stop_stream_listener();
+                exit_tx.send(()).unwrap();
+            }
+        }
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    ///
+    /// Members as this node sees them, itself included.
+    pub(crate) fn member_list(&self) -> Vec<ArtilleryMember> {
+        self.members.all_nodes()
+    }
+
+    fn probe_interval(&self) -> Result<Duration> {
+        std_duration(self.awareness.scale_timeout(self.config.ping_interval))
+    }
+
+    fn retransmit_limit(&self) -> usize {
+        dissemination::retransmit_limit(
+            self.config.retransmit_mult,
+            self.members.available_nodes().len(),
+        )
+    }
+
+    fn suspicion_bounds(&self) -> SuspicionBounds {
+        SuspicionBounds::new(&self.config, self.members.available_nodes().len())
+    }
+
+    ///
+    /// Wakes the event loop up to process queued requests right away.
+    pub(crate) fn request_waker(&self) -> Arc<Waker> {
+        self.stream_waker.clone()
+    }
+
+    ///
+    /// Number of outgoing messages dropped because they couldn't be built or sent.
+    pub fn dropped_sends(&self) -> u64 {
+        self.metrics.dropped_sends.get()
+    }
+
+    fn start_leave(&mut self, waiter: EventSender<LeaveStatus>) {
+        if let Some(progress) = self.leaving.as_mut() {
+            progress.waiters.push(waiter);
+            return;
+        }
+
+        let myself = self.members.leave();
+        self.state_changes.enqueue(myself);
+
+        let remote_members = self
+            .members
+            .available_nodes()
+            .iter()
+            .filter(|m| m.is_remote() && m.state() == ArtilleryMemberState::Alive)
+            .count();
+
+        self.leaving = Some(LeaveProgress {
+            deadline: runtime::now() + self.config.leave_timeout,
+            confirmations: self.config.leave_confirmations.min(remote_members),
+            acked_by: HashSet::new(),
+            waiters: vec![waiter],
+        });
+
+        self.gossip_leave();
+    }
+
+    ///
+    /// Sends our `Left` state to every alive member which didn't ack it yet.
+    fn gossip_leave(&mut self) {
+        self.finish_leave();
+
+        let targets: Vec<SocketAddr> = if let Some(progress) = self.leaving.as_ref() {
+            self.members
+                .available_nodes()
+                .iter()
+                .filter(|m| m.state() == ArtilleryMemberState::Alive)
+                .filter_map(ArtilleryMember::remote_host)
+                .filter(|addr| !progress.acked_by.contains(addr))
+                .collect()
+        } else {
+            return;
+        };
+
+        let myself = self.members.to_map().remove(&self.host_key);
+        for target in targets {
+            // Keep the leave on top of the piggyback queue until it is confirmed.
+            if let Some(ref member) = myself {
+                self.state_changes.enqueue(member.clone());
+            }
+
+            self.send_request(&TargetedRequest {
+                request: Request::Heartbeat,
+                target,
+            });
+        }
+    }
+
+    fn finish_leave(&mut self) {
+        let status = match self.leaving.as_ref() {
+            Some(progress) if progress.acked_by.len() >= progress.confirmations => {
+                LeaveStatus::Confirmed
+            }
+            Some(progress) if progress.deadline < runtime::now() => LeaveStatus::TimedOut,
+            Some(_) | None => return,
+        };
+
+        if let Some(progress) = self.leaving.take() {
+            info!("Leave finished: {:?}", status);
+
+            for waiter in progress.waiters {
+                let _ = waiter.send(status);
+            }
+        }
+    }
+
+    ///
+    /// Message carrying the request alone, see [`build_message`].
+    fn message_header(&self, request: &Request) -> ArtilleryMessage {
+        ArtilleryMessage {
+            sender: self.host_key,
+            sender_addr: self.config.advertise_addr,
+            cluster_key: self.outgoing_cluster_key().to_vec(),
+            request: request.clone(),
+            state_changes: Vec::new(),
+            broadcasts: Vec::new(),
+        }
+    }
+
+    fn outgoing_cluster_key(&self) -> &[u8] {
+        // Sealed packets are authenticated by the keyring,
+        // the cluster key must not travel in plaintext then.
+        if self.keyring.is_some() {
+            &[]
+        } else {
+            &self.config.cluster_key
+        }
+    }
+
+    fn send_request(&mut self, request: &TargetedRequest) {
+        if let Err(e) = self.process_request(request) {
+            self.metrics.dropped_sends.inc();
+            warn!(
+                "Dropped {:?} to {} ({} dropped so far): {}",
+                request.request,
+                request.target,
+                self.metrics.dropped_sends.get(),
+                e
+            );
+        }
+    }
+
+    fn process_request(&mut self, request: &TargetedRequest) -> Result<()> {
         use Request::*;
 
-        let timeout = Utc::now() + self.config.ping_timeout;
+        let timeout = runtime::now() + self.awareness.scale_timeout(self.config.ping_timeout);
         // It was Ping before
         let should_add_pending = request.request == Heartbeat;
         let message = build_message(
-            &self.host_key,
-            &self.config.cluster_key,
-            &request.request,
-            &self.state_changes,
+            self.message_header(&request.request),
+            &self.state_changes.prioritized(),
+            &self.broadcasts.prioritized(),
             self.config.network_mtu,
+            self.keyring.as_ref(),
+        )?;
+
+        let version = wire::negotiate_version(
+            self.config.protocol_version,
+            self.peer_versions.get(&request.target).cloned(),
         );
+        let encoded = wire::encode(version, &message, self.keyring.as_ref())?;
+
+        let target = network::outgoing(request.target, self.transport.local_addr()?);
+        if let Err(e) = self.transport.send_to(&encoded, target) {
+            bail!(
+                ArtilleryError::Send,
+                "sending {} bytes to {} failed: {}",
+                encoded.len(),
+                request.target,
+                e
+            );
+        }
 
-        if should_add_pending {
-            self.pending_responses
-                .push((timeout, request.target, message.state_changes.clone()));
+        self.metrics.packets_sent.inc();
+        self.metrics
+            .sent_packet_sizes
+            .observe(u64::try_from(encoded.len())?);
+        match request.request {
+            Heartbeat => self.metrics.probes.inc(),
+            Ping(_) => self.metrics.indirect_probes.inc(),
+            Ack(_) | AckHost(_) | Payload(_) | ReliablePayload(..) | PayloadAck(_) | Query(_)
+            | QueryAck(_) | QueryAnswer(..) => {}
         }
 
-        let encoded = serde_json::to_string(&message).unwrap();
+        if should_add_pending {
+            self.pending_responses.push((
+                timeout,
+                request.target,
+                message.state_changes.clone(),
+                runtime::now(),
+            ));
+        }
 
-        assert!(encoded.len() < self.config.network_mtu);
+        let limit = self.retransmit_limit();
+        self.state_changes
+            .mark_transmitted(&message.state_changes, limit);
+        self.broadcasts.mark_transmitted(&message.broadcasts, limit);
 
-        let buf = encoded.as_bytes();
-        self.server_socket.send_to(buf, request.target).unwrap();
+        Ok(())
     }
 
     fn enqueue_seed_nodes(&self) {
@@ -248,6 +786,121 @@ impl ArtilleryEpidemic {
         }
     }
 
+    fn queue_seed(&mut self, addr: SocketAddr) {
+        let own_addrs = [self.config.advertise_addr, self.transport.local_addr().ok()];
+        if self.seed_queue.contains(&addr) || own_addrs.contains(&Some(addr)) {
+            return;
+        }
+
+        // Joining node catches up with the whole cluster at once.
+        self.seed_queue.push(addr);
+        self.start_push_pull(addr, None);
+    }
+
+    fn resolve_seed_host(&self, host: String) {
+        seeds::spawn_resolution(
+            self.config.seed_resolver.clone(),
+            host,
+            (*self.request_tx).clone(),
+            self.stream_waker.clone(),
+        );
+    }
+
+    fn resolve_seed_hosts(&self) {
+        for host in self.seeds.hosts() {
+            self.resolve_seed_host(host);
+        }
+    }
+
+    ///
+    /// Starts over from the seeds once no other member is alive anymore.
+    fn reseed_if_isolated(&mut self) {
+        let left = self.leaving.is_some()
+            || self
+                .members
+                .get_member(&self.host_key)
+                .map(|myself| myself.state() == ArtilleryMemberState::Left)
+                == Some(true);
+        if left || !self.seed_queue.is_empty() || self.members.random_alive_host().is_some() {
+            return;
+        }
+
+        let seeds = self.seeds.all();
+        if seeds.is_empty() {
+            return;
+        }
+
+        info!(
+            "No member is alive, rejoining through {} seeds",
+            seeds.len()
+        );
+        for addr in seeds {
+            self.queue_seed(addr);
+        }
+        self.resolve_seed_hosts();
+    }
+
+    fn own_incarnation(&self) -> Option<u64> {
+        self.members
+            .get_member(&self.host_key)
+            .map(|myself| myself.incarnation_number())
+    }
+
+    fn incarnation_changed(&self) -> bool {
+        self.config.snapshot_path.is_some() && self.snapshot_incarnation != self.own_incarnation()
+    }
+
+    ///
+    /// Writes the snapshot if one is configured. Failures are only logged, the cluster
+    /// keeps running without it.
+    fn save_snapshot(&mut self) {
+        let (path, incarnation_number) = match (&self.config.snapshot_path, self.own_incarnation())
+        {
+            (Some(path), Some(incarnation_number)) => (path, incarnation_number),
+            (_, _) => return,
+        };
+
+        let snapshot = Snapshot {
+            host_key: self.host_key,
+            incarnation_number,
+            peers: self.members.reachable_hosts(),
+        };
+        match snapshot.save(path) {
+            Ok(()) => self.snapshot_incarnation = Some(incarnation_number),
+            Err(e) => warn!("Failed to write snapshot to {}: {}", path.display(), e),
+        }
+    }
+
+    fn record_gauges(&self) {
+        let members = self.members.available_nodes();
+        let count_in = |state| {
+            let count = members.iter().filter(|m| m.state() == state).count();
+            u64::try_from(count).unwrap_or(u64::MAX)
+        };
+
+        self.metrics
+            .alive_members
+            .set(count_in(ArtilleryMemberState::Alive));
+        self.metrics
+            .suspect_members
+            .set(count_in(ArtilleryMemberState::Suspect));
+        self.metrics
+            .piggyback_queue_depth
+            .set(u64::try_from(self.state_changes.len()).unwrap_or(u64::MAX));
+        self.metrics
+            .broadcast_queue_depth
+            .set(u64::try_from(self.broadcasts.len()).unwrap_or(u64::MAX));
+        self.metrics
+            .quarantined_sources
+            .set(u64::try_from(self.quarantine.len()).unwrap_or(u64::MAX));
+    }
+
+    fn enqueue_push_pull(&self) {
+        if let Some(target) = self.members.random_alive_host() {
+            self.start_push_pull(target, None);
+        }
+    }
+
     fn enqueue_random_ping(&mut self) {
         if let Some(member) = self.members.next_random_member() {
             self.request_tx
@@ -260,22 +913,28 @@ impl ArtilleryEpidemic {
     }
 
     fn prune_timed_out_responses(&mut self) {
-        let now = Utc::now();
+        let now = runtime::now();
 
         let (expired, remaining): (Vec<_>, Vec<_>) = self
             .pending_responses
             .iter()
             .cloned()
-            .partition(|&(t, _, _)| t < now);
+            .partition(|&(t, _, _, _)| t < now);
 
-        let expired_hosts: HashSet<SocketAddr> = expired.iter().map(|&(_, a, _)| a).collect();
+        let expired_hosts: HashSet<SocketAddr> = expired.iter().map(|&(_, a, _, _)| a).collect();
 
         self.pending_responses = remaining;
+        self.confirm_address_changes(&expired_hosts);
 
-        let (suspect, down) = self.members.time_out_nodes(&expired_hosts);
+        // Every unanswered probe is a hint that we might be the unhealthy one.
+        let failed_probes = i32::try_from(expired_hosts.len()).unwrap_or(i32::MAX);
+        self.awareness.apply_delta(failed_probes);
 
-        enqueue_state_change(&mut self.state_changes, &down);
-        enqueue_state_change(&mut self.state_changes, &suspect);
+        let bounds = self.suspicion_bounds();
+        let (suspect, down) = self.members.time_out_nodes(&expired_hosts, &bounds);
+
+        self.state_changes.enqueue_all(&down);
+        self.enqueue_state_changes(&suspect);
 
         for member in suspect {
             self.send_ping_requests(&member);
@@ -287,6 +946,25 @@ impl ArtilleryEpidemic {
         }
     }
 
+    fn reap_members(&mut self) {
+        let reaped = self.members.reap(
+            self.config.down_reap_timeout,
+            self.config.left_reap_timeout,
+            self.config.tombstone_timeout,
+        );
+
+        for member in reaped {
+            if let Some(addr) = member.remote_host() {
+                self.peer_versions.remove(&addr);
+                self.wait_list.remove(&addr);
+            }
+            self.member_coordinates.remove(&member.host_key());
+            self.coordinates.forget(&member.host_key());
+
+            self.send_member_event(ArtilleryMemberEvent::Reaped(member));
+        }
+    }
+
     fn send_ping_requests(&self, target: &ArtilleryMember) {
         if let Some(target_host) = target.remote_host() {
             for relay in self
@@ -307,35 +985,82 @@ impl ArtilleryEpidemic {
         use ArtilleryClusterRequest::*;
 
         match message {
-            AddSeed(addr) => self.seed_queue.push(addr),
+            AddSeed(seed_addr) => {
+                let addr = network::canonical(seed_addr);
+
+                self.seeds.add_addr(addr);
+                self.queue_seed(addr);
+            }
+            AddSeedHost(host) => {
+                self.seeds.add_host(host.clone());
+                self.resolve_seed_host(host);
+            }
+            SeedsResolved(host, addrs) => {
+                for addr in self.seeds.update_host(&host, addrs) {
+                    if !self.members.has_member_at(&addr) {
+                        self.queue_seed(addr);
+                    }
+                }
+            }
             Respond(src_addr, message) => self.respond_to_message(src_addr, message),
             React(request) => {
                 self.prune_timed_out_responses();
-                self.process_request(&request);
+                self.send_request(&request);
             }
             LeaveCluster => {
                 let myself = self.members.leave();
-                enqueue_state_change(&mut self.state_changes, &[myself]);
+                self.state_changes.enqueue(myself);
             }
-            Payload(id, msg) => {
-                if let Some(target_peer) = self.members.get_member(&id) {
-                    if !target_peer.is_remote() {
-                        error!("Current node can't send payload to self over LAN");
-                        return None;
-                    }
-
-                    self.process_request(&TargetedRequest {
-                        request: Request::Payload(id, msg),
-                        target: target_peer
-                            .remote_host()
-                            .expect("Expected target peer addr"),
-                    });
-                    return None;
+            Leave(tx) => self.start_leave(tx),
+            Payload(id, payload) => {
+                if let Err(e) = self.send_payload(id, payload) {
+                    warn!("Dropping payload to {}: {}", id, e);
                 }
-                warn!(
-                    "Unable to find the peer with an id - {} to send the payload",
-                    id
+            }
+            DeliverPayload(id, payload, tx) => self.deliver_payload(id, payload, tx),
+            Subscribe(topic, tx) => self.payload_router.subscribe(topic, tx),
+            Query(query, tx) => self.start_query(query, tx),
+            Coordinates(tx) => {
+                let mut coordinates = self.member_coordinates.clone();
+                coordinates.insert(self.host_key, self.coordinates.coordinate().clone());
+
+                let _ = tx.send(coordinates);
+            }
+            AnswerQuery(reply_to, id, response) => self.answer_query(reply_to, id, response),
+            Broadcast(topic, payload, tx) => {
+                let _ = tx.send(self.broadcast(topic, payload));
+            }
+            Keyring(request, tx) => {
+                let _ = tx.send(self.update_keyring(request));
+            }
+            SetMetadata(metadata, tx) => {
+                let _ = tx.send(self.set_metadata(metadata));
+            }
+            Reconfigure(update, tx) => {
+                let _ = tx.send(self.reconfigure(&update));
+            }
+            Stream(src_addr, frame, tx) => match self.handle_stream_frame(src_addr, &frame) {
+                Ok(answer) => {
+                    let _ = tx.send(answer);
+                }
+                Err(e) => warn!("Rejecting stream from {}: {}", src_addr, e),
+            },
+            SyncWith(addr, tx) => self.start_push_pull(addr, tx),
+            StreamResponse(addr, frame, tx) => {
+                let result = wire::decode(&frame, self.keyring.as_ref()).and_then(
+                    |(_, answer): (u8, StreamMessage)| self.handle_stream_answer(addr, answer),
                 );
+
+                reply_to_sync(tx, result);
+            }
+            SubscribeEvents(tx, config, reply) => {
+                let _ = reply.send(self.events.subscribe(tx, &config));
+            }
+            RegisterDelegate(delegate, kinds, reply) => {
+                let _ = reply.send(self.events.register(delegate, kinds));
+            }
+            UnsubscribeEvents(id, reply) => {
+                let _ = reply.send(self.events.unsubscribe(id));
             }
             Exit(tx) => return Some(tx),
         };
@@ -343,112 +1068,943 @@ impl ArtilleryEpidemic {
         None
     }
 
-    fn respond_to_message(&mut self, src_addr: SocketAddr, message: ArtilleryMessage) {
-        use Request::*;
+    ///
+    /// Our complete member list, as sent during the push/pull.
+    fn local_state(&self) -> Result<PushPullState> {
+        Ok(PushPullState {
+            sender: self.host_key,
+            sender_addr: match self.config.advertise_addr {
+                Some(advertise_addr) => advertise_addr,
+                None => self.transport.local_addr()?,
+            },
+            cluster_key: self.outgoing_cluster_key().to_vec(),
+            members: self.members.all_nodes(),
+        })
+    }
 
-        if message.cluster_key == self.config.cluster_key {
-            self.apply_state_changes(message.state_changes, src_addr);
-            remove_potential_seed(&mut self.seed_queue, src_addr);
+    fn start_push_pull(&self, target: SocketAddr, reply: Option<Sender<Result<()>>>) {
+        // Members only sync through the gossip on transports without streams.
+        if reply.is_none() && !self.transport.supports_streams() {
+            return;
+        }
 
-            self.ensure_node_is_member(src_addr, message.sender);
+        match self.local_state() {
+            Ok(state) => self.exchange_stream(target, &StreamMessage::PushPull(state), reply),
+            Err(e) => reply_to_sync(reply, Err(e)),
+        }
+    }
 
-            let response = match message.request {
-                Heartbeat => Some(TargetedRequest {
-                    request: Ack,
-                    target: src_addr,
-                }),
-                Ack => {
-                    self.ack_response(src_addr);
-                    self.mark_node_alive(src_addr);
-                    None
-                }
-                Ping(dest_addr) => {
-                    let EncSocketAddr(dest_addr) = dest_addr;
-                    add_to_wait_list(&mut self.wait_list, &dest_addr, &src_addr);
-                    Some(TargetedRequest {
-                        request: Heartbeat,
-                        target: dest_addr,
-                    })
-                }
-                AckHost(member) => {
-                    self.ack_response(member.remote_host().unwrap());
-                    self.mark_node_alive(member.remote_host().unwrap());
-                    None
-                }
-                Payload(peer_id, msg) => {
-                    if let Some(member) = self.members.get_member(&peer_id) {
-                        self.send_member_event(ArtilleryMemberEvent::Payload(member, msg));
-                    } else {
-                        warn!("Got payload request from an unknown peer {}", peer_id);
+    ///
+    /// Sends the message over a stream of the transport, its answer comes back as a
+    /// `StreamResponse`.
+    fn exchange_stream(
+        &self,
+        target: SocketAddr,
+        message: &StreamMessage,
+        reply: Option<Sender<Result<()>>>,
+    ) {
+        let request = std_duration(self.config.stream_timeout).and_then(|timeout| {
+            let version = wire::negotiate_version(
+                self.config.protocol_version,
+                self.peer_versions.get(&target).cloned(),
+            );
+
+            Ok((
+                wire::encode(version, message, self.keyring.as_ref())?,
+                timeout,
+            ))
+        });
+
+        match request {
+            Ok((frame, timeout)) => {
+                let request_tx = (*self.request_tx).clone();
+                let waker = self.stream_waker.clone();
+                let on_answer: StreamAnswer = Box::new(move |answer| match answer {
+                    Ok(response) => {
+                        let _ = request_tx.send(ArtilleryClusterRequest::StreamResponse(
+                            target, response, reply,
+                        ));
+                        let _ = waker.wake();
                     }
-                    None
-                }
-            };
+                    Err(e) => reply_to_sync(reply, Err(e.into())),
+                });
 
-            if let Some(response) = response {
-                self.request_tx
-                    .send(ArtilleryClusterRequest::React(response))
-                    .unwrap()
+                self.transport
+                    .exchange_stream(target, frame, timeout, on_answer);
             }
-        } else {
-            error!("Mismatching cluster keys, ignoring message");
+            Err(e) => reply_to_sync(reply, Err(e)),
         }
     }
 
-    fn ack_response(&mut self, src_addr: SocketAddr) {
-        let mut to_remove = Vec::new();
+    fn handle_stream_frame(&mut self, src_addr: SocketAddr, frame: &[u8]) -> Result<Vec<u8>> {
+        let (version, message) = wire::decode(frame, self.keyring.as_ref())?;
+
+        let answer = match message {
+            StreamMessage::PushPull(remote) => {
+                self.merge_push_pull(src_addr, remote)?;
+                StreamMessage::PushPull(self.local_state()?)
+            }
+            StreamMessage::Payload(payload) => {
+                if self.keyring.is_none() && payload.cluster_key != self.config.cluster_key {
+                    bail!(ArtilleryError::Unexpected, "Mismatching cluster keys");
+                }
 
-        for &(ref t, ref addr, ref state_changes) in &self.pending_responses {
-            if src_addr != *addr {
-                continue;
+                self.receive_reliable_payload(payload.sender, payload.seq, payload.payload)?;
+                StreamMessage::PayloadAck(payload.seq)
             }
+            StreamMessage::PayloadAck(_) => {
+                bail!(ArtilleryError::Unexpected, "Unsolicited payload ack");
+            }
+        };
 
-            to_remove.push((*t, *addr, state_changes.clone()));
+        wire::encode(
+            wire::negotiate_version(self.config.protocol_version, Some(version)),
+            &answer,
+            self.keyring.as_ref(),
+        )
+    }
 
-            self.state_changes.retain(|os| {
-                !state_changes
-                    .iter()
-                    .any(|is| is.member().host_key() == os.member().host_key())
-            })
+    fn handle_stream_answer(&mut self, src_addr: SocketAddr, answer: StreamMessage) -> Result<()> {
+        match answer {
+            StreamMessage::PushPull(remote) => self.merge_push_pull(src_addr, remote),
+            StreamMessage::PayloadAck(seq) => {
+                self.complete_payload(seq, DeliveryStatus::Delivered);
+                Ok(())
+            }
+            StreamMessage::Payload(_) => {
+                bail!(ArtilleryError::Unexpected, "Payload is not a stream answer");
+            }
+        }
+    }
+
+    fn send_payload(&mut self, target: Uuid, payload: ArtilleryPayload) -> Result<()> {
+        let target_peer = match self.members.get_member(&target) {
+            Some(member) => member,
+            None => {
+                bail!(ArtilleryError::Send, "Unable to find the peer with an id");
+            }
+        };
+        let target_addr = match target_peer.remote_host() {
+            Some(addr) if target_peer.is_remote() => addr,
+            Some(_) | None => {
+                bail!(
+                    ArtilleryError::Send,
+                    "Current node can't send payload to self over LAN"
+                );
+            }
+        };
+
+        self.send_request(&TargetedRequest {
+            request: Request::Payload(payload),
+            target: target_addr,
+        });
+
+        Ok(())
+    }
+
+    fn deliver_payload(
+        &mut self,
+        target: Uuid,
+        payload: ArtilleryPayload,
+        waiter: EventSender<DeliveryStatus>,
+    ) {
+        match self.members.get_member(&target) {
+            Some(ref member) if member.is_remote() => {}
+            Some(_) | None => {
+                let _ = waiter.send(DeliveryStatus::UnknownPeer);
+                return;
+            }
+        }
+
+        let now = runtime::now();
+        self.payload_seq += 1;
+        self.outgoing_payloads.insert(
+            self.payload_seq,
+            PendingPayload {
+                target,
+                payload,
+                deadline: now + self.config.payload_timeout,
+                next_attempt: now,
+                backoff: self.config.payload_retry_interval,
+                waiter,
+            },
+        );
+
+        self.retry_payloads();
+    }
+
+    fn retry_payloads(&mut self) {
+        let now = runtime::now();
+        let due: Vec<u64> = self
+            .outgoing_payloads
+            .iter()
+            .filter(|(_, pending)| pending.next_attempt <= now)
+            .map(|(seq, _)| *seq)
+            .collect();
+
+        for seq in due {
+            self.attempt_payload(seq, now);
+        }
+    }
+
+    ///
+    /// Time until the next payload attempt or query deadline is due.
+    fn next_timer(&self) -> Result<Option<Duration>> {
+        let now = runtime::now();
+
+        let payload_attempts = self.outgoing_payloads.values().map(|p| p.next_attempt);
+        let query_deadlines = self.queries.values().map(|q| q.deadline);
+
+        match payload_attempts.chain(query_deadlines).min() {
+            Some(next_timer) => Ok(Some(std_duration(
+                (next_timer - now).max(chrono::Duration::zero()),
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    fn attempt_payload(&mut self, seq: u64, now: DateTime<Utc>) {
+        let (target, payload) = match self.outgoing_payloads.get_mut(&seq) {
+            Some(pending) if pending.deadline >= now => {
+                // Last attempt is due at the deadline, where it times out.
+                pending.next_attempt = (now + pending.backoff).min(pending.deadline);
+                pending.backoff = pending.backoff * 2;
+                (pending.target, pending.payload.clone())
+            }
+            Some(_) | None => {
+                self.complete_payload(seq, DeliveryStatus::TimedOut);
+                return;
+            }
+        };
+
+        let target_addr = if let Some(addr) = self
+            .members
+            .get_member(&target)
+            .as_ref()
+            .and_then(ArtilleryMember::remote_host)
+        {
+            addr
+        } else {
+            self.complete_payload(seq, DeliveryStatus::UnknownPeer);
+            return;
+        };
+
+        let request = Request::ReliablePayload(seq, payload.clone());
+        if self.fits_into_packet(&request) {
+            self.send_request(&TargetedRequest {
+                request,
+                target: target_addr,
+            });
+        } else {
+            let message = StreamMessage::Payload(StreamPayload {
+                sender: self.host_key,
+                cluster_key: self.outgoing_cluster_key().to_vec(),
+                seq,
+                payload,
+            });
+
+            self.exchange_stream(target_addr, &message, None);
+        }
+    }
+
+    fn complete_payload(&mut self, seq: u64, status: DeliveryStatus) {
+        if let Some(pending) = self.outgoing_payloads.remove(&seq) {
+            let _ = pending.waiter.send(status);
+        }
+    }
+
+    ///
+    /// Hands an acknowledged payload over to the event stream, once per sequence number.
+    fn receive_reliable_payload(
+        &mut self,
+        sender: Uuid,
+        seq: u64,
+        payload: ArtilleryPayload,
+    ) -> Result<()> {
+        let member = match self.members.get_member(&sender) {
+            Some(member) => member,
+            None => {
+                bail!(
+                    ArtilleryError::Unexpected,
+                    format!("Got payload from an unknown peer {}", sender)
+                );
+            }
+        };
+
+        let now = runtime::now();
+        let dedup_window = self.config.payload_timeout * 2;
+        self.delivered_payloads
+            .retain(|_, delivered_at| *delivered_at + dedup_window >= now);
+
+        if self.delivered_payloads.insert((sender, seq), now).is_none() {
+            self.receive_payload(member, payload);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Routes the payload to the subscribers of its topic, or the event stream.
+    fn receive_payload(&mut self, member: ArtilleryMember, payload: ArtilleryPayload) {
+        if let Some((sender, unrouted)) = self.payload_router.route(member, payload) {
+            self.send_member_event(ArtilleryMemberEvent::Payload(sender, unrouted));
+        }
+    }
+
+    ///
+    /// Asks every alive member matching the filter, this one included. Responses are
+    /// collected until the query times out, which ends their stream.
+    fn start_query(&mut self, mut query: ArtilleryQuery, responses: EventSender<QueryResponse>) {
+        let deadline = match chrono::Duration::from_std(query.timeout) {
+            Ok(timeout) => runtime::now() + timeout,
+            Err(e) => {
+                warn!("Dropping query {}: {}", query.name, e);
+                return;
+            }
+        };
+
+        self.query_seq += 1;
+        query.id = self.query_seq;
+        self.queries.insert(
+            query.id,
+            PendingQuery {
+                deadline,
+                responses,
+            },
+        );
+
+        let targets: Vec<ArtilleryMember> = self
+            .members
+            .available_nodes()
+            .into_iter()
+            .filter(|m| m.state() == ArtilleryMemberState::Alive && query.filter.matches(m))
+            .collect();
+
+        for target in targets {
+            if let Some(addr) = target.remote_host() {
+                self.send_request(&TargetedRequest {
+                    request: Request::Query(query.clone()),
+                    target: addr,
+                });
+            } else {
+                self.collect_query_response(query.id, QueryResponse::Ack(target.clone()));
+                self.deliver_query(target, query.clone(), None);
+            }
+        }
+    }
+
+    fn deliver_query(
+        &mut self,
+        from: ArtilleryMember,
+        query: ArtilleryQuery,
+        reply_to: Option<SocketAddr>,
+    ) {
+        let incoming = IncomingQuery::new(
+            from,
+            query,
+            reply_to,
+            (*self.request_tx).clone(),
+            self.stream_waker.clone(),
+        );
+
+        match incoming {
+            Ok(received) => self.send_member_event(ArtilleryMemberEvent::Query(received)),
+            Err(e) => warn!("Dropping query: {}", e),
+        }
+    }
+
+    fn answer_query(&mut self, reply_to: Option<SocketAddr>, id: u64, response: Vec<u8>) {
+        match reply_to {
+            Some(target) => self.send_request(&TargetedRequest {
+                request: Request::QueryAnswer(id, response),
+                target,
+            }),
+            None => {
+                if let Some(myself) = self.members.get_member(&self.host_key) {
+                    self.collect_query_response(id, QueryResponse::Response(myself, response));
+                }
+            }
+        }
+    }
+
+    fn collect_query_response(&mut self, id: u64, response: QueryResponse) {
+        let delivered = match self.queries.get(&id) {
+            Some(query) => query.responses.send(response).is_ok(),
+            None => return,
+        };
+
+        // Nobody is waiting for the responses anymore.
+        if !delivered {
+            self.queries.remove(&id);
+        }
+    }
+
+    fn expire_queries(&mut self) {
+        let now = runtime::now();
+
+        // Dropping the sender ends the stream of responses.
+        self.queries.retain(|_, query| query.deadline >= now);
+    }
+
+    fn broadcast(&mut self, topic: String, payload: Vec<u8>) -> Result<()> {
+        let broadcast = ArtilleryBroadcast {
+            id: runtime::random_uuid(),
+            origin: self.host_key,
+            lamport: self.broadcast_log.tick(),
+            topic,
+            payload,
+        };
+
+        let probe = build_message(
+            self.message_header(&Request::Heartbeat),
+            &[],
+            std::slice::from_ref(&broadcast),
+            self.config.network_mtu,
+            self.keyring.as_ref(),
+        )?;
+        if probe.broadcasts.is_empty() {
+            bail!(
+                ArtilleryError::Send,
+                "broadcast doesn't fit into the network MTU of {} bytes",
+                self.config.network_mtu
+            );
+        }
+
+        self.receive_broadcast(broadcast);
+
+        Ok(())
+    }
+
+    ///
+    /// Delivers a broadcast seen for the first time and passes it on.
+    fn receive_broadcast(&mut self, broadcast: ArtilleryBroadcast) {
+        if self
+            .broadcast_log
+            .observe(&broadcast, self.config.broadcast_dedup_window)
+        {
+            self.broadcasts.enqueue(broadcast.clone());
+            self.send_member_event(ArtilleryMemberEvent::Broadcast(broadcast));
+        }
+    }
+
+    fn fits_into_packet(&self, request: &Request) -> bool {
+        build_message(
+            self.message_header(request),
+            &[],
+            &[],
+            self.config.network_mtu,
+            self.keyring.as_ref(),
+        )
+        .is_ok()
+    }
+
+    ///
+    /// Merges the member list of a remote node into ours, keeping the most up to date
+    /// data of every member.
+    fn merge_push_pull(&mut self, src_addr: SocketAddr, remote: PushPullState) -> Result<()> {
+        if self.keyring.is_none() && remote.cluster_key != self.config.cluster_key {
+            bail!(ArtilleryError::Unexpected, "Mismatching cluster keys");
+        }
+
+        // Stream came from an ephemeral port, the sender tells us where it gossips.
+        let sender_addr = if remote.sender_addr.ip().is_unspecified() {
+            SocketAddr::new(src_addr.ip(), remote.sender_addr.port())
+        } else {
+            remote.sender_addr
+        };
+
+        if !self.check_identity(sender_addr, remote.sender) {
+            bail!(
+                ArtilleryError::Unexpected,
+                format!(
+                    "Host key {} of {} is in conflict",
+                    remote.sender, sender_addr
+                )
+            );
+        }
+
+        let state_changes = remote
+            .members
+            .into_iter()
+            .map(ArtilleryStateChange::new)
+            .collect();
+
+        self.apply_state_changes(state_changes, sender_addr, remote.sender);
+        remove_potential_seed(&mut self.seed_queue, sender_addr);
+
+        Ok(())
+    }
+
+    fn stop_stream_listener(&self) {
+        if let Ok(timeout) = std_duration(self.config.stream_timeout) {
+            let listeners = self
+                .transport
+                .stream_listen_addr()
+                .into_iter()
+                .chain(self.metrics_listen_addr);
+
+            for addr in listeners {
+                stream::wake_listener(addr, timeout);
+            }
+        }
+    }
+
+    fn set_metadata(&mut self, metadata: BTreeMap<String, String>) -> Result<()> {
+        let mut candidate = ArtilleryMember::current(self.host_key);
+        candidate.set_metadata(metadata.clone());
+
+        // Metadata which can't be piggybacked would never leave this node.
+        let probe = build_message(
+            self.message_header(&Request::Heartbeat),
+            &[ArtilleryStateChange::new(candidate)],
+            &[],
+            self.config.network_mtu,
+            self.keyring.as_ref(),
+        )?;
+        if probe.state_changes.is_empty() {
+            bail!(
+                ArtilleryError::Send,
+                "metadata doesn't fit into the network MTU of {} bytes",
+                self.config.network_mtu
+            );
+        }
+
+        let myself = self.members.set_metadata(metadata);
+        self.state_changes.enqueue(myself);
+
+        Ok(())
+    }
+
+    ///
+    /// Swaps the timings in. The probe interval is recomputed on every loop iteration,
+    /// so they take effect right away.
+    fn reconfigure(&mut self, update: &ClusterConfigUpdate) -> Result<()> {
+        let config = self.config.updated(update)?;
+
+        // Shrinking the MTU must not leave our own state behind.
+        if let Some(myself) = self.members.get_member(&self.host_key) {
+            let probe = build_message(
+                self.message_header(&Request::Heartbeat),
+                &[ArtilleryStateChange::new(myself)],
+                &[],
+                config.network_mtu,
+                self.keyring.as_ref(),
+            )?;
+            if probe.state_changes.is_empty() {
+                bail!(
+                    ArtilleryError::Config,
+                    "this member doesn't fit into the network MTU of {} bytes",
+                    config.network_mtu
+                );
+            }
+        }
+
+        info!("Reconfigured cluster: {:?}", update);
+        self.config = config;
+
+        Ok(())
+    }
+
+    fn update_keyring(&mut self, request: KeyringRequest) -> Result<()> {
+        let keyring = match self.keyring.as_mut() {
+            Some(keyring) => keyring,
+            None => {
+                bail!(ArtilleryError::Keyring, "gossip encryption is disabled");
+            }
+        };
+
+        match request {
+            KeyringRequest::Install(key) => {
+                keyring.install_key(&key);
+                Ok(())
+            }
+            KeyringRequest::Use(key) => keyring.use_key(&key),
+            KeyringRequest::Remove(key) => keyring.remove_key(&key),
+        }
+    }
+
+    fn respond_to_message(&mut self, src_addr: SocketAddr, message: ArtilleryMessage) {
+        use Request::*;
+
+        if self.keyring.is_some() || message.cluster_key == self.config.cluster_key {
+            // Members are known by the address they advertise, replies go to the source.
+            let from = match message.sender_addr {
+                Some(sender_addr) => network::canonical(sender_addr),
+                None => src_addr,
+            };
+
+            if !self.check_identity(from, message.sender) {
+                self.hold_message(from, src_addr, message);
+                return;
+            }
+
+            // Hearing from the member itself proves it is alive, even if it restarted
+            // without a snapshot and its incarnation is behind its tombstone.
+            self.members.forget_tombstone(&message.sender);
+            self.apply_state_changes(message.state_changes, from, message.sender);
+            for broadcast in message.broadcasts {
+                self.receive_broadcast(broadcast);
+            }
+            remove_potential_seed(&mut self.seed_queue, src_addr);
+            remove_potential_seed(&mut self.seed_queue, from);
+
+            self.ensure_node_is_member(from, message.sender);
+
+            let response = match message.request {
+                Heartbeat => Some(TargetedRequest {
+                    request: Ack(self.coordinates.coordinate().clone()),
+                    target: src_addr,
+                }),
+                Ack(coordinate) => {
+                    self.awareness.apply_delta(-1);
+                    if let Some(rtt) = self.ack_response(from) {
+                        self.update_coordinates(message.sender, coordinate, rtt);
+                    }
+                    self.mark_node_alive(from);
+                    None
+                }
+                Ping(dest_addr) => {
+                    let EncSocketAddr(dest_addr) = dest_addr;
+                    add_to_wait_list(&mut self.wait_list, &dest_addr, &src_addr);
+                    Some(TargetedRequest {
+                        request: Heartbeat,
+                        target: dest_addr,
+                    })
+                }
+                AckHost(member) => {
+                    match member.remote_host() {
+                        Some(host) => {
+                            self.ack_response(host);
+                            self.mark_node_alive(host);
+                        }
+                        None => self.record_bad_packet(src_addr, "ack without a host"),
+                    }
+                    None
+                }
+                Payload(payload) => {
+                    if let Some(member) = self.members.get_member(&message.sender) {
+                        self.receive_payload(member, payload);
+                    } else {
+                        warn!(
+                            "Got payload request from an unknown peer {}",
+                            message.sender
+                        );
+                    }
+                    None
+                }
+                ReliablePayload(seq, payload) => {
+                    match self.receive_reliable_payload(message.sender, seq, payload) {
+                        Ok(()) => Some(TargetedRequest {
+                            request: PayloadAck(seq),
+                            target: src_addr,
+                        }),
+                        Err(e) => {
+                            warn!("Dropping payload from {}: {}", src_addr, e);
+                            None
+                        }
+                    }
+                }
+                Query(query) => {
+                    let myself = self.members.get_member(&self.host_key);
+                    let querier = self.members.get_member(&message.sender);
+
+                    match (myself, querier) {
+                        (Some(ref local), Some(asking)) if query.filter.matches(local) => {
+                            let id = query.id;
+                            self.deliver_query(asking, query, Some(src_addr));
+                            Some(TargetedRequest {
+                                request: QueryAck(id),
+                                target: src_addr,
+                            })
+                        }
+                        _ => None,
+                    }
+                }
+                QueryAck(id) => {
+                    if let Some(member) = self.members.get_member(&message.sender) {
+                        self.collect_query_response(id, QueryResponse::Ack(member));
+                    }
+                    None
+                }
+                QueryAnswer(id, response) => {
+                    if let Some(member) = self.members.get_member(&message.sender) {
+                        self.collect_query_response(id, QueryResponse::Response(member, response));
+                    }
+                    None
+                }
+                PayloadAck(seq) => {
+                    // Only the target itself can ack its payload.
+                    let acked_by_target = self
+                        .outgoing_payloads
+                        .get(&seq)
+                        .map(|pending| pending.target)
+                        == Some(message.sender);
+                    if acked_by_target {
+                        self.complete_payload(seq, DeliveryStatus::Delivered);
+                    }
+                    None
+                }
+            };
+
+            if let Some(response) = response {
+                self.request_tx
+                    .send(ArtilleryClusterRequest::React(response))
+                    .unwrap()
+            }
+        } else {
+            self.record_bad_packet(src_addr, "mismatching cluster keys");
+        }
+    }
+
+    ///
+    /// Counts a packet of the source which had to be dropped, quarantining the source
+    /// once it sent too many of them.
+    fn record_bad_packet(&mut self, source: SocketAddr, reason: &str) {
+        let failures = self
+            .quarantine
+            .record_failure(source, &self.config.quarantine);
+        warn!(
+            "Dropping packet from {} ({} bad packets lately): {}",
+            source, failures, reason
+        );
+
+        if self.quarantine.is_quarantined(source) && failures == self.config.quarantine.threshold {
+            error!(
+                "Quarantining {} for {}s after {} bad packets",
+                source,
+                self.config.quarantine.period.num_seconds(),
+                failures
+            );
+        }
+    }
+
+    ///
+    /// Returns `true` if the message of `sender` coming from `src_addr` can be merged.
+    ///
+    /// A known live member showing up at a new address is either a restarted node
+    /// or another node sharing its host key. Its old address is probed to tell them
+    /// apart, and its messages are held back until then. They are answered if it
+    /// turns out to have moved, and dropped if it turns out to be an impostor.
+    fn check_identity(&mut self, src_addr: SocketAddr, sender: Uuid) -> bool {
+        if sender == self.host_key {
+            if let Some(myself) = self.members.get_member(&self.host_key) {
+                error!("Node at {} claims our host key {}", src_addr, sender);
+                self.report_conflict(myself, src_addr);
+            }
+            return false;
+        }
+
+        if self.conflicts.get(&src_addr) == Some(&sender) {
+            return false;
+        }
+
+        let known = match self.members.get_member(&sender) {
+            Some(member) => member,
+            None => return true,
+        };
+        let old_addr = match known.remote_host() {
+            Some(addr) if addr != src_addr => addr,
+            Some(_) | None => return true,
+        };
+
+        if known.state() != ArtilleryMemberState::Alive {
+            self.change_member_address(sender, old_addr, src_addr);
+            return true;
+        }
+
+        if let Entry::Vacant(entry) = self.address_checks.entry(sender) {
+            entry.insert((old_addr, src_addr));
+            self.send_request(&TargetedRequest {
+                request: Request::Heartbeat,
+                target: old_addr,
+            });
+        }
+
+        false
+    }
+
+    ///
+    /// Holds the message back if its sender's move to `from` is being checked.
+    fn hold_message(&mut self, from: SocketAddr, src_addr: SocketAddr, message: ArtilleryMessage) {
+        let checked = match self.address_checks.get(&message.sender) {
+            Some(&(_, new_addr)) => new_addr == from,
+            None => false,
+        };
+        if !checked {
+            return;
+        }
+
+        let held = self.held_messages.entry(message.sender).or_default();
+        if held.len() < MAX_HELD_MESSAGES {
+            held.push((src_addr, message));
+        }
+    }
+
+    fn confirm_address_changes(&mut self, expired_hosts: &HashSet<SocketAddr>) {
+        let confirmed: Vec<_> = self
+            .address_checks
+            .iter()
+            .filter(|(_, (old_addr, _))| expired_hosts.contains(old_addr))
+            .map(|(host_key, &(old_addr, new_addr))| (*host_key, old_addr, new_addr))
+            .collect();
+
+        for (host_key, old_addr, new_addr) in confirmed {
+            self.address_checks.remove(&host_key);
+            self.change_member_address(host_key, old_addr, new_addr);
+
+            for (src_addr, message) in self.held_messages.remove(&host_key).unwrap_or_default() {
+                self.respond_to_message(src_addr, message);
+            }
+        }
+    }
+
+    fn change_member_address(
+        &mut self,
+        host_key: Uuid,
+        old_addr: SocketAddr,
+        new_addr: SocketAddr,
+    ) {
+        if let Some(member) = self.members.change_address(&host_key, new_addr) {
+            info!(
+                "Member {} moved from {} to {}",
+                host_key, old_addr, new_addr
+            );
+
+            self.peer_versions.remove(&old_addr);
+            self.conflicts.retain(|_, key| *key != host_key);
+            self.state_changes.enqueue(member.clone());
+            self.send_member_event(ArtilleryMemberEvent::AddressChanged(old_addr, member));
+        }
+    }
+
+    fn report_conflict(&mut self, member: ArtilleryMember, claimant: SocketAddr) {
+        if self.conflicts.insert(claimant, member.host_key()) != Some(member.host_key()) {
+            self.send_member_event(ArtilleryMemberEvent::Conflict(member, claimant));
+        }
+    }
+
+    ///
+    /// Settles the probes of the acking host. Returns the RTT of the oldest one.
+    fn ack_response(&mut self, src_addr: SocketAddr) -> Option<Duration> {
+        let my_host_key = self.host_key;
+        let rtt = self
+            .pending_responses
+            .iter()
+            .filter(|(_, addr, _, _)| *addr == src_addr)
+            .filter_map(|(_, _, _, sent_at)| (runtime::now() - *sent_at).to_std().ok())
+            .max();
+        let acked_leave = self
+            .pending_responses
+            .iter()
+            .any(|(_, addr, state_changes, _)| {
+                *addr == src_addr
+                    && state_changes.iter().any(|sc| {
+                        sc.member().host_key() == my_host_key
+                            && sc.member().state() == ArtilleryMemberState::Left
+                    })
+            });
+
+        // Acked state changes stay queued, they are retired by the retransmit limit.
+        self.pending_responses
+            .retain(|&(_, addr, _, _)| addr != src_addr);
+
+        // Old address is still alive, so the new one belongs to an impostor.
+        let conflicting: Vec<_> = self
+            .address_checks
+            .iter()
+            .filter(|(_, (old_addr, _))| *old_addr == src_addr)
+            .map(|(host_key, &(_, new_addr))| (*host_key, new_addr))
+            .collect();
+
+        for (host_key, new_addr) in conflicting {
+            self.address_checks.remove(&host_key);
+            self.held_messages.remove(&host_key);
+
+            if let Some(member) = self.members.get_member(&host_key) {
+                error!(
+                    "Nodes at {} and {} share host key {}",
+                    src_addr, new_addr, host_key
+                );
+                self.report_conflict(member, new_addr);
+            }
+        }
+
+        if acked_leave {
+            if let Some(progress) = self.leaving.as_mut() {
+                progress.acked_by.insert(src_addr);
+            }
+            self.finish_leave();
+        }
+
+        rtt
+    }
+
+    fn update_coordinates(&mut self, sender: Uuid, coordinate: Coordinate, rtt: Duration) {
+        if self.coordinates.update(sender, &coordinate, rtt) {
+            self.member_coordinates.insert(sender, coordinate);
+        } else {
+            debug!("Rejected coordinate of {}: {:?}", sender, coordinate);
         }
-
-        self.pending_responses
-            .retain(|op| !to_remove.iter().any(|ip| ip == op));
     }
 
     fn ensure_node_is_member(&mut self, src_addr: SocketAddr, sender: Uuid) {
-        if self.members.has_member(&src_addr) {
+        if self.members.has_member(&sender) {
             return;
         }
 
         let new_member = ArtilleryMember::new(sender, src_addr, 0, ArtilleryMemberState::Alive);
-
         self.members.add_member(new_member.clone());
-        enqueue_state_change(&mut self.state_changes, &[new_member.clone()]);
+        self.state_changes.enqueue(new_member.clone());
         self.send_member_event(ArtilleryMemberEvent::Joined(new_member));
     }
 
-    fn send_member_event(&self, event: ArtilleryMemberEvent) {
+    fn send_member_event(&mut self, event: ArtilleryMemberEvent) {
         use ArtilleryMemberEvent::*;
 
         match event {
-            Joined(_) | Payload(..) => {}
-            WentUp(ref m) => assert_eq!(m.state(), ArtilleryMemberState::Alive),
-            WentDown(ref m) => assert_eq!(m.state(), ArtilleryMemberState::Down),
-            SuspectedDown(ref m) => assert_eq!(m.state(), ArtilleryMemberState::Suspect),
+            Joined(_) | Updated(_) | Reaped(_) | AddressChanged(..) | Conflict(..)
+            | Payload(..) | Broadcast(_) | Query(_) => {}
+            WentUp(ref m) => {
+                assert_eq!(m.state(), ArtilleryMemberState::Alive);
+                if m.is_remote() {
+                    self.metrics.recoveries.inc();
+                }
+            }
+            WentDown(ref m) => {
+                assert_eq!(m.state(), ArtilleryMemberState::Down);
+                self.metrics.failures.inc();
+            }
+            SuspectedDown(ref m) => {
+                assert_eq!(m.state(), ArtilleryMemberState::Suspect);
+                self.metrics.suspicions.inc();
+            }
             Left(ref m) => assert_eq!(m.state(), ArtilleryMemberState::Left),
         };
 
-        self.event_tx
-            .send((self.members.available_nodes(), event))
-            .unwrap();
+        let members = self.members.available_nodes();
+        self.events.dispatch(&(members, event), &self.metrics);
     }
 
-    fn apply_state_changes(&mut self, state_changes: Vec<ArtilleryStateChange>, from: SocketAddr) {
-        let (new, changed) = self.members.apply_state_changes(state_changes, &from);
+    fn apply_state_changes(
+        &mut self,
+        state_changes: Vec<ArtilleryStateChange>,
+        from: SocketAddr,
+        sender: Uuid,
+    ) {
+        let bounds = self.suspicion_bounds();
+        let (new, changed, updated) =
+            self.members
+                .apply_state_changes(state_changes, &from, &sender, &bounds);
+
+        // Refuting a suspicion about ourselves degrades our local health.
+        if changed.iter().any(ArtilleryMember::is_current) {
+            self.awareness.apply_delta(1);
+            self.metrics.refutations.inc();
+        }
 
-        enqueue_state_change(&mut self.state_changes, &new);
-        enqueue_state_change(&mut self.state_changes, &changed);
+        self.enqueue_state_changes(&new);
+        self.enqueue_state_changes(&changed);
+        self.enqueue_state_changes(&updated);
 
         for member in new {
             self.send_member_event(ArtilleryMemberEvent::Joined(member));
@@ -457,6 +2013,31 @@ impl ArtilleryEpidemic {
         for member in changed {
             self.send_member_event(determine_member_event(member));
         }
+
+        for member in updated {
+            self.send_member_event(ArtilleryMemberEvent::Updated(member));
+        }
+    }
+
+    ///
+    /// Queues the state changes of the given members. Suspected members carry their
+    /// original suspector along, so that passing a suspicion on doesn't count as
+    /// confirming it.
+    fn enqueue_state_changes(&mut self, members: &[ArtilleryMember]) {
+        for member in members {
+            let suspector = match member.state() {
+                ArtilleryMemberState::Suspect => self.members.suspector_of(&member.host_key()),
+                ArtilleryMemberState::Alive
+                | ArtilleryMemberState::Down
+                | ArtilleryMemberState::Left => None,
+            };
+
+            let state_change = match suspector {
+                Some(origin) => ArtilleryStateChange::suspected(member.clone(), origin),
+                None => ArtilleryStateChange::new(member.clone()),
+            };
+            self.state_changes.enqueue_change(state_change);
+        }
     }
 
     fn mark_node_alive(&mut self, src_addr: SocketAddr) {
@@ -474,42 +2055,116 @@ impl ArtilleryEpidemic {
                 wait_list.clear();
             }
 
-            enqueue_state_change(&mut self.state_changes, &[member.clone()]);
+            self.state_changes.enqueue(member.clone());
             self.send_member_event(ArtilleryMemberEvent::WentUp(member));
         }
     }
 }
 
+///
+/// Builds a message which fits into the network MTU, piggybacking as many of the
+/// given state changes and then broadcasts as possible in their order of priority.
+/// The header is expected to carry none of them yet.
 fn build_message(
-    sender: &Uuid,
-    cluster_key: &[u8],
-    request: &Request,
+    header: ArtilleryMessage,
     state_changes: &[ArtilleryStateChange],
+    broadcasts: &[ArtilleryBroadcast],
     network_mtu: usize,
-) -> ArtilleryMessage {
-    let mut message = ArtilleryMessage {
-        sender: *sender,
-        cluster_key: cluster_key.into(),
-        request: request.clone(),
-        state_changes: Vec::new(),
-    };
+    keyring: Option<&Keyring>,
+) -> Result<ArtilleryMessage> {
+    let mut message = header;
+
+    let mut packet_len = wire::encoded_len(&message, keyring)?;
+    if packet_len > network_mtu {
+        bail!(
+            ArtilleryError::Send,
+            "message of {} bytes doesn't fit into the network MTU of {} bytes",
+            packet_len,
+            network_mtu
+        );
+    }
 
-    for i in 0..=state_changes.len() {
+    // Every state change adds its own encoded size to the packet.
+    for state_change in state_changes {
         flunk!("epidemic-state-change-tail-follow-fp");
-        message = ArtilleryMessage {
-            sender: *sender,
-            cluster_key: cluster_key.into(),
-            request: request.clone(),
-            state_changes: (&state_changes[..i]).to_vec(),
-        };
+        let state_change_len = wire::encoded_body_len(state_change)?;
+
+        if packet_len + state_change_len <= network_mtu {
+            packet_len += state_change_len;
+            message.state_changes.push(state_change.clone());
+        }
+    }
+
+    for broadcast in broadcasts {
+        let broadcast_len = wire::encoded_body_len(broadcast)?;
+
+        if packet_len + broadcast_len <= network_mtu {
+            packet_len += broadcast_len;
+            message.broadcasts.push(broadcast.clone());
+        }
+    }
+
+    // Length prefixes of the lists might have grown as well.
+    while wire::encoded_len(&message, keyring)? > network_mtu {
+        if message.broadcasts.pop().is_none() {
+            message.state_changes.pop();
+        }
+    }
+
+    Ok(message)
+}
 
-        let encoded = serde_json::to_string(&message).unwrap();
-        if encoded.len() >= network_mtu {
-            return message;
+///
+/// Snapshot of the previous run, a missing or unreadable one starts us from scratch.
+fn load_snapshot(config: &ClusterConfig) -> Option<Snapshot> {
+    let path = config.snapshot_path.as_ref()?;
+
+    match Snapshot::load(path) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            warn!("Ignoring snapshot at {}: {}", path.display(), e);
+            None
         }
     }
+}
+
+fn validate_config(config: &ClusterConfig) -> Result<()> {
+    config.validate()?;
+
+    if !wire::is_supported_version(config.protocol_version) {
+        bail!(
+            ArtilleryError::ProtocolVersion,
+            "configured protocol version {} is not supported",
+            config.protocol_version
+        );
+    }
+
+    if let Some(advertise_addr) = config.advertise_addr {
+        network::validate_advertise_addr(advertise_addr)?;
+    }
+
+    Ok(())
+}
 
-    message
+// Time passed since the given instant of the epidemic clock.
+fn elapsed_since(instant: DateTime<Utc>) -> Duration {
+    (runtime::now() - instant).to_std().unwrap_or_default()
+}
+
+fn std_duration(duration: chrono::Duration) -> Result<Duration> {
+    Ok(Duration::from_millis(u64::try_from(
+        duration.num_milliseconds(),
+    )?))
+}
+
+fn reply_to_sync(reply: Option<Sender<Result<()>>>, result: Result<()>) {
+    if let Err(ref e) = result {
+        warn!("Stream exchange failed: {}", e);
+    }
+
+    if let Some(tx) = reply {
+        let _ = tx.send(result);
+    }
 }
 
 fn add_to_wait_list(wait_list: &mut WaitList, wait_addr: &SocketAddr, notify_addr: &SocketAddr) {
@@ -536,24 +2191,500 @@ fn determine_member_event(member: ArtilleryMember) -> ArtilleryMemberEvent {
     }
 }
 
-fn enqueue_state_change(
-    state_changes: &mut Vec<ArtilleryStateChange>,
-    members: &[ArtilleryMember],
-) {
-    for member in members {
-        for state_change in state_changes.iter_mut() {
-            if state_change.member().host_key() == member.host_key() {
-                state_change.update(member.clone());
-                return;
+impl EncSocketAddr {
+    fn from_addr(addr: &SocketAddr) -> Self {
+        EncSocketAddr(*addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        build_message, ArtilleryClusterEvent, ArtilleryClusterRequest, ArtilleryEpidemic,
+        ArtilleryMemberEvent, ArtilleryMessage, DeliveryStatus, Request,
+    };
+    use crate::constants::{CONST_PACKET_SIZE, CONST_PROTOCOL_VERSION};
+    use crate::epidemic::broadcast::ArtilleryBroadcast;
+    use crate::epidemic::cluster_config::ClusterConfig;
+    use crate::epidemic::keyring::Keyring;
+    use crate::epidemic::member::{ArtilleryMember, ArtilleryMemberState, ArtilleryStateChange};
+    use crate::epidemic::payload::ArtilleryPayload;
+    use crate::epidemic::runtime;
+    use crate::epidemic::simulation::{Simulation, SimulationConfig};
+    use crate::epidemic::stream::StreamMessage;
+    use crate::epidemic::transport::{StreamAnswer, Transport};
+    use crate::epidemic::wire;
+    use crate::errors::*;
+    use crate::events::{event_channel, EventReceiver};
+    use chrono::{Duration, Utc};
+    use mio::{Registry, Token, Waker};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    const LOCAL: &str = "127.0.0.1:27001";
+    const PEER: &str = "127.0.0.1:27002";
+
+    // Transport keeping whatever is sent over it.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        packets: Arc<Mutex<Vec<(SocketAddr, Vec<u8>)>>>,
+        streams: Arc<Mutex<Vec<(SocketAddr, Vec<u8>, StreamAnswer)>>>,
+    }
+
+    impl Recorder {
+        // Requests sent to the address since the last call.
+        fn take_requests(&self, target: SocketAddr) -> Vec<Request> {
+            let mut packets = self.packets.lock().unwrap();
+            packets
+                .drain(..)
+                .filter(|(to, _)| *to == target)
+                .map(|(_, packet)| {
+                    let (_, message): (u8, ArtilleryMessage) = wire::decode(&packet, None).unwrap();
+                    message.request
+                })
+                .collect()
+        }
+    }
+
+    impl Transport for Recorder {
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(LOCAL.parse().unwrap())
+        }
+
+        fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+            self.packets.lock().unwrap().push((target, packet.to_vec()));
+            Ok(packet.len())
+        }
+
+        fn recv_from(&self, _: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+
+        fn register(&mut self, _: &Registry, _: Token, _: Arc<Waker>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn supports_streams(&self) -> bool {
+            true
+        }
+
+        fn exchange_stream(
+            &self,
+            target: SocketAddr,
+            frame: Vec<u8>,
+            _: std::time::Duration,
+            on_answer: StreamAnswer,
+        ) {
+            self.streams
+                .lock()
+                .unwrap()
+                .push((target, frame, on_answer));
+        }
+    }
+
+    struct Peer {
+        host_key: Uuid,
+        addr: SocketAddr,
+    }
+
+    impl Peer {
+        fn message(&self, request: Request) -> ArtilleryMessage {
+            ArtilleryMessage {
+                sender: self.host_key,
+                sender_addr: Some(self.addr),
+                cluster_key: ClusterConfig::default().cluster_key,
+                request,
+                state_changes: Vec::new(),
+                broadcasts: Vec::new(),
             }
         }
+    }
 
-        state_changes.push(ArtilleryStateChange::new(member.clone()));
+    struct Harness {
+        state: ArtilleryEpidemic,
+        requests: Receiver<ArtilleryClusterRequest>,
+        events: EventReceiver<ArtilleryClusterEvent>,
+        recorder: Recorder,
+        peer: Peer,
     }
-}
 
-impl EncSocketAddr {
-    fn from_addr(addr: &SocketAddr) -> Self {
-        EncSocketAddr(*addr)
+    impl Harness {
+        // Epidemic knowing a single alive peer, which never answers on its own.
+        fn new(config: ClusterConfig) -> Result<Self> {
+            let recorder = Recorder::default();
+            let (event_tx, events) = event_channel();
+            let (request_tx, requests) = channel();
+            let (_, mut state) = ArtilleryEpidemic::with_transport(
+                Uuid::new_v4(),
+                ClusterConfig {
+                    listen_addr: LOCAL.parse().unwrap(),
+                    ..config
+                },
+                event_tx,
+                request_tx,
+                Box::new(recorder.clone()),
+            )?;
+
+            let peer = Peer {
+                host_key: Uuid::new_v4(),
+                addr: PEER.parse().unwrap(),
+            };
+            state.members.add_member(ArtilleryMember::new(
+                peer.host_key,
+                peer.addr,
+                0,
+                ArtilleryMemberState::Alive,
+            ));
+
+            Ok(Harness {
+                state,
+                requests,
+                events,
+                recorder,
+                peer,
+            })
+        }
+
+        fn receive(&mut self, message: &ArtilleryMessage) -> Result<()> {
+            let packet = wire::encode(CONST_PROTOCOL_VERSION, message, None)?;
+            self.state.receive_packet(self.peer.addr, &packet)?;
+            self.state.process_requests(&self.requests);
+
+            Ok(())
+        }
+
+        fn deliver(&mut self, target: Uuid, data: Vec<u8>) -> EventReceiver<DeliveryStatus> {
+            let (tx, rx) = event_channel();
+            self.state
+                .deliver_payload(target, ArtilleryPayload::new("test", data), tx);
+            rx
+        }
+
+        fn payload_events(&self) -> usize {
+            self.events
+                .try_iter()
+                .filter(|(_, event)| match event {
+                    ArtilleryMemberEvent::Payload(..) => true,
+                    _ => false,
+                })
+                .count()
+        }
+    }
+
+    fn reliable_seqs(requests: &[Request]) -> Vec<u64> {
+        requests
+            .iter()
+            .filter_map(|request| match request {
+                Request::ReliablePayload(seq, _) => Some(*seq),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn simulation(seed: u64) -> Simulation {
+        Simulation::new(SimulationConfig {
+            seed,
+            cluster: ClusterConfig {
+                ping_interval: Duration::milliseconds(200),
+                ping_timeout: Duration::milliseconds(100),
+                ..ClusterConfig::default()
+            },
+            ..SimulationConfig::default()
+        })
+    }
+
+    fn start(simulation: &mut Simulation, size: usize) -> Result<Vec<SocketAddr>> {
+        let nodes = (0..size)
+            .map(|_| simulation.add_node())
+            .collect::<Result<Vec<_>>>()?;
+        for node in &nodes[1..] {
+            simulation.join(*node, nodes[0])?;
+        }
+        assert!(simulation.run_until(Duration::seconds(30), Simulation::converged)?);
+
+        Ok(nodes)
+    }
+
+    #[test]
+    fn test_messages_held_during_address_check_are_answered() -> Result<()> {
+        let mut simulation = simulation(1);
+        let nodes = start(&mut simulation, 3)?;
+
+        // Member restarted elsewhere, its first message starts the check of its old
+        // address.
+        let moved: SocketAddr = "10.9.9.11:27845".parse().unwrap();
+        simulation.kill(nodes[2])?;
+        let message = ArtilleryMessage {
+            sender: simulation.host_key(nodes[2])?,
+            sender_addr: Some(moved),
+            cluster_key: ClusterConfig::default().cluster_key,
+            request: Request::Payload(ArtilleryPayload::new("moved", vec![1])),
+            state_changes: Vec::new(),
+            broadcasts: Vec::new(),
+        };
+        simulation.inject(
+            moved,
+            nodes[0],
+            &wire::encode(CONST_PROTOCOL_VERSION, &message, None)?,
+        );
+        simulation.run_for(Duration::seconds(1))?;
+
+        let events: Vec<_> = simulation
+            .trace()
+            .iter()
+            .filter(|o| o.node == nodes[0])
+            .filter_map(|o| match &o.event {
+                ArtilleryMemberEvent::AddressChanged(..) => Some("moved"),
+                ArtilleryMemberEvent::Payload(..) => Some("payload"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(events, vec!["moved", "payload"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload_is_delivered_once_acked() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig::default())?;
+        let peer = harness.peer.addr;
+
+        let status = harness.deliver(harness.peer.host_key, vec![1]);
+        let seqs = reliable_seqs(&harness.recorder.take_requests(peer));
+        assert_eq!(seqs.len(), 1);
+        assert!(status.try_recv().is_err());
+
+        // Nobody but the target can ack it.
+        let mut impostor = harness.peer.message(Request::PayloadAck(seqs[0]));
+        impostor.sender = Uuid::new_v4();
+        impostor.sender_addr = Some("127.0.0.1:27003".parse().unwrap());
+        harness.receive(&impostor)?;
+        assert!(status.try_recv().is_err());
+
+        harness.receive(&harness.peer.message(Request::PayloadAck(seqs[0])))?;
+        assert_eq!(status.try_recv().ok(), Some(DeliveryStatus::Delivered));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload_to_unknown_peer_fails() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig::default())?;
+
+        let status = harness.deliver(Uuid::new_v4(), vec![1]);
+        assert_eq!(status.try_recv().ok(), Some(DeliveryStatus::UnknownPeer));
+
+        let myself = harness.state.host_key;
+        let status = harness.deliver(myself, vec![1]);
+        assert_eq!(status.try_recv().ok(), Some(DeliveryStatus::UnknownPeer));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payload_retries_back_off_until_timed_out() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig {
+            payload_timeout: Duration::seconds(1),
+            payload_retry_interval: Duration::milliseconds(100),
+            ..ClusterConfig::default()
+        })?;
+        let peer = harness.peer.addr;
+
+        let status = harness.deliver(harness.peer.host_key, vec![1]);
+        let mut attempts = vec![(0, reliable_seqs(&harness.recorder.take_requests(peer)))];
+        for elapsed in 1..=110 {
+            runtime::advance(Duration::milliseconds(10));
+            harness.state.run_timers();
+
+            let seqs = reliable_seqs(&harness.recorder.take_requests(peer));
+            if !seqs.is_empty() {
+                attempts.push((elapsed * 10, seqs));
+            }
+        }
+
+        // Doubling intervals, the last attempt right at the deadline.
+        let times: Vec<_> = attempts.iter().map(|(at, _)| *at).collect();
+        assert_eq!(times, vec![0, 100, 300, 700, 1000]);
+        assert!(attempts.iter().all(|(_, seqs)| seqs == &attempts[0].1));
+        assert_eq!(status.try_recv().ok(), Some(DeliveryStatus::TimedOut));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_payloads_are_deduplicated_within_the_window() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig {
+            payload_timeout: Duration::seconds(1),
+            ..ClusterConfig::default()
+        })?;
+        let peer = harness.peer.addr;
+        let payload = Request::ReliablePayload(7, ArtilleryPayload::new("test", vec![1]));
+
+        // Retries are acked every time, but handed over once.
+        for _ in 0..3 {
+            harness.receive(&harness.peer.message(payload.clone()))?;
+            runtime::advance(Duration::milliseconds(500));
+        }
+        let acks = harness.recorder.take_requests(peer);
+        assert_eq!(
+            acks.iter()
+                .filter(|request| **request == Request::PayloadAck(7))
+                .count(),
+            3
+        );
+        assert_eq!(harness.payload_events(), 1);
+
+        // Remembered for twice the payload timeout since it was last seen.
+        runtime::advance(Duration::milliseconds(1400));
+        harness.receive(&harness.peer.message(payload.clone()))?;
+        assert_eq!(harness.payload_events(), 0);
+
+        runtime::advance(Duration::milliseconds(2100));
+        harness.receive(&harness.peer.message(payload))?;
+        assert_eq!(harness.payload_events(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_payload_falls_back_to_a_stream() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig::default())?;
+        let peer = harness.peer.addr;
+
+        let status = harness.deliver(harness.peer.host_key, vec![0; CONST_PACKET_SIZE]);
+        assert!(reliable_seqs(&harness.recorder.take_requests(peer)).is_empty());
+
+        let (target, frame, on_answer) = harness.recorder.streams.lock().unwrap().remove(0);
+        assert_eq!(target, peer);
+        let seq = match wire::decode(&frame, None)? {
+            (_, StreamMessage::Payload(payload)) => payload.seq,
+            (_, message) => panic!("Expected a payload, got {:?}", message),
+        };
+
+        let ack = StreamMessage::PayloadAck(seq);
+        on_answer(Ok(wire::encode(CONST_PROTOCOL_VERSION, &ack, None)?));
+        harness.state.process_requests(&harness.requests);
+        assert_eq!(status.try_recv().ok(), Some(DeliveryStatus::Delivered));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_is_trimmed_to_the_mtu() -> Result<()> {
+        let peer = Peer {
+            host_key: Uuid::new_v4(),
+            addr: PEER.parse().unwrap(),
+        };
+        let state_changes: Vec<_> = (0..50)
+            .map(|_| {
+                ArtilleryStateChange::new(ArtilleryMember::new(
+                    Uuid::new_v4(),
+                    peer.addr,
+                    0,
+                    ArtilleryMemberState::Alive,
+                ))
+            })
+            .collect();
+        let broadcasts: Vec<_> = (0..20)
+            .map(|lamport| ArtilleryBroadcast {
+                id: Uuid::new_v4(),
+                origin: peer.host_key,
+                lamport,
+                topic: "test".to_string(),
+                payload: vec![0; 100],
+            })
+            .collect();
+        let keyring = Keyring::new(b"cluster", &[]);
+
+        for ring in &[None, Some(&keyring)] {
+            let message = build_message(
+                peer.message(Request::Heartbeat),
+                &state_changes,
+                &broadcasts,
+                600,
+                *ring,
+            )?;
+
+            // Highest priority first, as much as fits.
+            assert!(wire::encoded_len(&message, *ring)? <= 600);
+            assert!(!message.state_changes.is_empty());
+            assert!(message.state_changes.len() < state_changes.len());
+            assert_eq!(
+                message.state_changes[..],
+                state_changes[..message.state_changes.len()]
+            );
+            assert_eq!(
+                message.broadcasts[..],
+                broadcasts[..message.broadcasts.len()]
+            );
+        }
+
+        // Broadcasts fill what the state changes leave over.
+        let message = build_message(
+            peer.message(Request::Heartbeat),
+            &state_changes[..2],
+            &broadcasts,
+            600,
+            None,
+        )?;
+        assert_eq!(message.state_changes.len(), 2);
+        assert!(!message.broadcasts.is_empty());
+        assert!(message.broadcasts.len() < broadcasts.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oversized_message_is_dropped_and_counted() -> Result<()> {
+        let _clock = runtime::simulate(Utc::now(), 1);
+        let mut harness = Harness::new(ClusterConfig {
+            network_mtu: 600,
+            ..ClusterConfig::default()
+        })?;
+        let peer = harness.peer.addr;
+
+        let payload = ArtilleryPayload::new("test", vec![0; 600]);
+        assert!(build_message(
+            harness.peer.message(Request::Payload(payload.clone())),
+            &[],
+            &[],
+            600,
+            None
+        )
+        .is_err());
+
+        let host_key = harness.peer.host_key;
+        harness.state.send_payload(host_key, payload)?;
+        assert!(harness.recorder.take_requests(peer).is_empty());
+        assert_eq!(harness.state.dropped_sends(), 1);
+        assert_eq!(harness.state.metrics.packets_sent.get(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unusable_config_is_refused_at_construction() {
+        let (event_tx, _events) = event_channel();
+        let (request_tx, _requests) = channel();
+        let epidemic = ArtilleryEpidemic::with_transport(
+            Uuid::new_v4(),
+            ClusterConfig {
+                retransmit_mult: 0,
+                ..ClusterConfig::default()
+            },
+            event_tx,
+            request_tx,
+            Box::new(Recorder::default()),
+        );
+
+        assert!(epidemic.is_err());
     }
 }
@@ -0,0 +1,275 @@
+use crate::constants::*;
+use crate::epidemic::member::ArtilleryMember;
+use crate::epidemic::network;
+use crate::epidemic::payload::ArtilleryPayload;
+use crate::epidemic::state::ArtilleryClusterRequest;
+use bastion_executor::blocking::spawn_blocking;
+use lightproc::proc_stack::ProcStack;
+use mio::Waker;
+use serde::*;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Frames on the TCP streams are prefixed with their length as a big endian u32.
+const FRAME_LEN_PREFIX: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StreamMessage {
+    PushPull(PushPullState),
+    Payload(StreamPayload),
+    PayloadAck(u64),
+}
+
+///
+/// Complete member list of a node, exchanged during the push/pull anti-entropy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PushPullState {
+    pub sender: Uuid,
+    pub sender_addr: SocketAddr,
+    pub cluster_key: Vec<u8>,
+    pub members: Vec<ArtilleryMember>,
+}
+
+///
+/// Acknowledged payload which doesn't fit into a single packet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamPayload {
+    pub sender: Uuid,
+    pub cluster_key: Vec<u8>,
+    pub seq: u64,
+    pub payload: ArtilleryPayload,
+}
+
+pub(crate) fn write_frame<W: Write>(stream: &mut W, frame: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(frame.len())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(frame)?;
+    stream.flush()
+}
+
+pub(crate) fn read_frame<R: Read>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut len_prefix = [0_u8; FRAME_LEN_PREFIX];
+    stream.read_exact(&mut len_prefix)?;
+
+    let len = usize::try_from(u32::from_be_bytes(len_prefix))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    if len > CONST_STREAM_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("stream frame of {} bytes is too big", len),
+        ));
+    }
+
+    let mut frame = vec![0_u8; len];
+    stream.read_exact(&mut frame)?;
+
+    Ok(frame)
+}
+
+///
+/// Bounds the number of incoming streams served at once.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamSlots {
+    limit: usize,
+    taken: Arc<AtomicUsize>,
+}
+
+///
+/// Place of a stream being served, freed when dropped.
+#[derive(Debug)]
+pub(crate) struct StreamSlot {
+    taken: Arc<AtomicUsize>,
+}
+
+impl StreamSlots {
+    pub(crate) fn new(limit: usize) -> Self {
+        StreamSlots {
+            limit,
+            taken: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    ///
+    /// Takes a slot, unless all of them are taken.
+    pub(crate) fn try_take(&self) -> Option<StreamSlot> {
+        let limit = self.limit;
+        self.taken
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| {
+                if taken < limit {
+                    Some(taken + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| StreamSlot {
+                taken: self.taken.clone(),
+            })
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.taken.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+///
+/// Sends a frame to the given node and waits for its answer.
+pub(crate) fn exchange(target: SocketAddr, frame: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&target, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    write_frame(&mut stream, frame)?;
+    read_frame(&mut stream)
+}
+
+///
+/// Runs the exchange on a blocking task and hands its outcome to `on_answer`.
+pub(crate) fn spawn_exchange<F>(target: SocketAddr, frame: Vec<u8>, timeout: Duration, on_answer: F)
+where
+    F: FnOnce(io::Result<Vec<u8>>) + Send + 'static,
+{
+    let _exchange_handle = spawn_blocking(
+        async move { on_answer(exchange(target, &frame, timeout)) },
+        ProcStack::default(),
+    );
+}
+
+///
+/// Accepts the incoming TCP streams until `running` is unset. Every frame is handed
+/// over to the epidemic event loop, woken up by `waker`, and its answer is written
+/// back to the peer. Streams beyond `max_streams` at a time are refused.
+pub(crate) fn spawn_listener(
+    listener: TcpListener,
+    timeout: Duration,
+    max_streams: usize,
+    request_tx: Sender<ArtilleryClusterRequest>,
+    waker: Arc<Waker>,
+    running: Arc<AtomicBool>,
+) {
+    let slots = StreamSlots::new(max_streams);
+    let _listener_handle = spawn_blocking(
+        async move {
+            for incoming in listener.incoming() {
+                if !running.load(Ordering::SeqCst) {
+                    debug!("Stopping artillery epidemic stream listener");
+                    break;
+                }
+
+                match (incoming, slots.try_take()) {
+                    (Ok(stream), Some(slot)) => {
+                        let stream_request_tx = request_tx.clone();
+                        let stream_waker = waker.clone();
+                        let _stream_handle = spawn_blocking(
+                            async move {
+                                if let Err(e) =
+                                    serve_stream(stream, timeout, &stream_request_tx, &stream_waker)
+                                {
+                                    debug!("Epidemic stream closed: {}", e);
+                                }
+                                drop(slot);
+                            },
+                            ProcStack::default(),
+                        );
+                    }
+                    (Ok(stream), None) => {
+                        // Dropping the stream closes it.
+                        warn!(
+                            "Refusing epidemic stream from {:?}, already serving {} streams",
+                            stream.peer_addr(),
+                            max_streams
+                        );
+                    }
+                    (Err(e), _) => warn!("Failed to accept epidemic stream: {}", e),
+                }
+            }
+        },
+        ProcStack::default(),
+    );
+}
+
+///
+/// Wakes up the listener blocked on `accept`, so it notices the shutdown.
+pub(crate) fn wake_listener(listen_addr: SocketAddr, timeout: Duration) {
+    let mut addr = listen_addr;
+
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+
+    let _ = TcpStream::connect_timeout(&addr, timeout);
+}
+
+fn serve_stream(
+    mut stream: TcpStream,
+    timeout: Duration,
+    request_tx: &Sender<ArtilleryClusterRequest>,
+    waker: &Waker,
+) -> io::Result<()> {
+    let peer_addr = network::canonical(stream.peer_addr()?);
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let frame = read_frame(&mut stream)?;
+
+    let (reply_tx, reply_rx) = channel();
+    request_tx
+        .send(ArtilleryClusterRequest::Stream(peer_addr, frame, reply_tx))
+        .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))?;
+    waker.wake()?;
+
+    // Event loop drops the reply channel if it refuses the frame.
+    match reply_rx.recv_timeout(timeout) {
+        Ok(reply) => write_frame(&mut stream, &reply),
+        Err(e) => Err(io::Error::new(io::ErrorKind::TimedOut, e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_frame, write_frame, StreamSlots};
+    use crate::constants::CONST_STREAM_FRAME_SIZE;
+    use std::convert::TryFrom;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"members").unwrap();
+        write_frame(&mut buf, b"").unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert_eq!(read_frame(&mut cursor).unwrap(), b"members");
+        assert!(read_frame(&mut cursor).unwrap().is_empty());
+        assert!(read_frame(&mut cursor).is_err());
+
+        let oversized = u32::try_from(CONST_STREAM_FRAME_SIZE + 1).unwrap();
+        let mut cursor = Cursor::new(oversized.to_be_bytes().to_vec());
+        assert!(read_frame(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn test_streams_beyond_the_limit_are_refused() {
+        let slots = StreamSlots::new(2);
+        let first = slots.try_take();
+        let second = slots.try_take();
+        assert!(first.is_some() && second.is_some());
+        assert!(slots.try_take().is_none());
+
+        drop(first);
+        assert!(slots.try_take().is_some());
+    }
+}
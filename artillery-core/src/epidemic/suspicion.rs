@@ -0,0 +1,166 @@
+use super::cluster_config::ClusterConfig;
+use super::runtime;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+///
+/// Timeout boundaries of the suspicion timers for the current cluster size.
+#[derive(Debug, Clone, Copy)]
+pub struct SuspicionBounds {
+    pub min: Duration,
+    pub max: Duration,
+    pub expected_confirmations: usize,
+}
+
+impl SuspicionBounds {
+    pub fn new(config: &ClusterConfig, member_count: usize) -> Self {
+        let min = suspicion_timeout(config.suspicion_mult, member_count, config.ping_interval);
+        let max = min * i32::try_from(config.suspicion_max_timeout_mult).unwrap_or(i32::MAX);
+
+        // Lifeguard expects `mult - 2` independent confirmations, as long as the
+        // cluster is big enough to provide them.
+        let expected = usize::try_from(config.suspicion_mult.saturating_sub(2)).unwrap_or(0);
+        let expected_confirmations = if member_count.saturating_sub(2) < expected {
+            0
+        } else {
+            expected
+        };
+
+        SuspicionBounds {
+            min,
+            max,
+            expected_confirmations,
+        }
+    }
+}
+
+///
+/// Lifeguard suspicion timer of a single suspected member.
+///
+/// The timer starts from `max` and decays logarithmically towards `min` with every
+/// independent confirmation from other members. A suspicion shared by the cluster
+/// resolves quickly, while a lone suspicion gives the member time to refute it.
+#[derive(Debug, Clone)]
+pub struct Suspicion {
+    origin: Uuid,
+    suspectors: HashSet<Uuid>,
+    bounds: SuspicionBounds,
+    started: DateTime<Utc>,
+}
+
+impl Suspicion {
+    pub fn new(suspector: Uuid, bounds: SuspicionBounds) -> Self {
+        let mut suspectors = HashSet::new();
+        suspectors.insert(suspector);
+
+        Suspicion {
+            origin: suspector,
+            suspectors,
+            bounds,
+            started: runtime::now(),
+        }
+    }
+
+    ///
+    /// Records a suspicion coming from another member.
+    /// Returns `true` if this member didn't suspect before.
+    pub fn confirm(&mut self, suspector: Uuid) -> bool {
+        self.suspectors.insert(suspector)
+    }
+
+    ///
+    /// Member that started the suspicion.
+    pub fn origin(&self) -> Uuid {
+        self.origin
+    }
+
+    pub fn confirmations(&self) -> usize {
+        // Whoever started the suspicion doesn't count as a confirmation.
+        self.suspectors.len().saturating_sub(1)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        decayed_timeout(
+            self.confirmations(),
+            self.bounds.expected_confirmations,
+            self.bounds.min,
+            self.bounds.max,
+        )
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.started + self.timeout() < runtime::now()
+    }
+}
+
+///
+/// Minimum suspicion timeout, scaled with the logarithm of the cluster size.
+pub fn suspicion_timeout(
+    suspicion_mult: u32,
+    member_count: usize,
+    ping_interval: Duration,
+) -> Duration {
+    #![allow(
+        clippy::float_arithmetic,
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation
+    )]
+
+    let node_scale = (member_count.max(1) as f64).log10().max(1.0);
+    let interval = ping_interval.num_milliseconds() as f64;
+
+    Duration::milliseconds((f64::from(suspicion_mult) * node_scale * interval) as i64)
+}
+
+fn decayed_timeout(
+    confirmations: usize,
+    expected_confirmations: usize,
+    min: Duration,
+    max: Duration,
+) -> Duration {
+    #![allow(
+        clippy::float_arithmetic,
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation
+    )]
+
+    if expected_confirmations == 0 || max <= min {
+        return min;
+    }
+
+    let frac = (confirmations as f64 + 1.0).ln() / (expected_confirmations as f64 + 1.0).ln();
+    let span = (max - min).num_milliseconds() as f64;
+    let timeout = max.num_milliseconds() - (frac * span).floor() as i64;
+
+    Duration::milliseconds(timeout.max(min.num_milliseconds()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decayed_timeout, suspicion_timeout};
+    use chrono::Duration;
+
+    #[test]
+    fn test_suspicion_timeout_scales_with_cluster_size() {
+        let interval = Duration::seconds(1);
+
+        assert_eq!(suspicion_timeout(4, 1, interval), Duration::seconds(4));
+        assert_eq!(suspicion_timeout(4, 10, interval), Duration::seconds(4));
+        assert_eq!(suspicion_timeout(4, 1000, interval), Duration::seconds(12));
+    }
+
+    #[test]
+    fn test_suspicion_timeout_decays_with_confirmations() {
+        let min = Duration::seconds(2);
+        let max = Duration::seconds(30);
+
+        assert_eq!(decayed_timeout(0, 3, min, max), max);
+        assert!(decayed_timeout(1, 3, min, max) < max);
+        assert!(decayed_timeout(2, 3, min, max) < decayed_timeout(1, 3, min, max));
+        assert_eq!(decayed_timeout(3, 3, min, max), min);
+        assert_eq!(decayed_timeout(10, 3, min, max), min);
+        assert_eq!(decayed_timeout(0, 0, min, max), min);
+    }
+}
@@ -0,0 +1,351 @@
+use super::network;
+use super::stream;
+use mio::net::UdpSocket;
+#[cfg(unix)]
+use mio::net::UnixDatagram;
+use mio::{Interest, Registry, Token, Waker};
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+///
+/// Receives the answer of a stream exchange, or why there is none.
+pub type StreamAnswer = Box<dyn FnOnce(io::Result<Vec<u8>>) + Send>;
+
+///
+/// Carries the gossip packets of the epidemic, and optionally its push/pull streams.
+pub trait Transport: Send {
+    ///
+    /// Address the other members reach us at.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize>;
+
+    ///
+    /// Reads a packet without blocking, fails with `WouldBlock` when there is none.
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    ///
+    /// Lets the event loop know about incoming packets, either by registering with its
+    /// registry under the token or by waking it up through the waker.
+    fn register(&mut self, registry: &Registry, token: Token, waker: Arc<Waker>) -> io::Result<()>;
+
+    ///
+    /// Whether push/pull streams can be exchanged, without them members only sync
+    /// through the gossip.
+    fn supports_streams(&self) -> bool {
+        false
+    }
+
+    ///
+    /// Address of the TCP listener accepting the streams of other members, if any.
+    fn stream_listen_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    ///
+    /// Sends the frame to the target over a stream and hands its answer over.
+    fn exchange_stream(
+        &self,
+        _target: SocketAddr,
+        _frame: Vec<u8>,
+        _timeout: Duration,
+        on_answer: StreamAnswer,
+    ) {
+        on_answer(Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "transport has no streams",
+        )));
+    }
+}
+
+///
+/// UDP socket, with the streams over TCP on the same port.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(UdpTransport {
+            socket: UdpSocket::from_std(network::bind_udp(addr)?),
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(packet, target)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf)
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token, _: Arc<Waker>) -> io::Result<()> {
+        registry.register(&mut self.socket, token, Interest::READABLE)
+    }
+
+    fn supports_streams(&self) -> bool {
+        true
+    }
+
+    fn stream_listen_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    fn exchange_stream(
+        &self,
+        target: SocketAddr,
+        frame: Vec<u8>,
+        timeout: Duration,
+        on_answer: StreamAnswer,
+    ) {
+        stream::spawn_exchange(target, frame, timeout, on_answer);
+    }
+}
+
+///
+/// Unix datagram socket within a directory shared by the members, every member binds
+/// the socket named after its address there.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixDatagramTransport {
+    dir: PathBuf,
+    addr: SocketAddr,
+    socket: UnixDatagram,
+}
+
+#[cfg(unix)]
+impl UnixDatagramTransport {
+    pub fn bind<P: AsRef<Path>>(dir: P, addr: SocketAddr) -> io::Result<Self> {
+        let socket_path = dir.as_ref().join(addr.to_string());
+        // Socket of a previous run would fail the bind.
+        match fs::remove_file(&socket_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Ok(()) | Err(_) => {}
+        }
+
+        Ok(UnixDatagramTransport {
+            dir: dir.as_ref().to_path_buf(),
+            addr,
+            socket: UnixDatagram::bind(socket_path)?,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixDatagramTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.socket
+            .send_to(packet, self.dir.join(target.to_string()))
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (packet_size, source) = self.socket.recv_from(buf)?;
+        let member_addr = source
+            .as_pathname()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse().ok());
+
+        match member_addr {
+            Some(source_addr) => Ok((packet_size, source_addr)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet from a socket outside of the cluster: {:?}", source),
+            )),
+        }
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token, _: Arc<Waker>) -> io::Result<()> {
+        registry.register(&mut self.socket, token, Interest::READABLE)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixDatagramTransport {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.dir.join(self.addr.to_string()));
+    }
+}
+
+type Packet = (SocketAddr, Vec<u8>);
+
+struct Endpoint {
+    packets: Sender<Packet>,
+    waker: Option<Arc<Waker>>,
+}
+
+///
+/// Network within the process, for members embedded next to each other.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    endpoints: Arc<Mutex<HashMap<SocketAddr, Endpoint>>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut endpoints = self.endpoints();
+        if endpoints.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is taken", addr),
+            ));
+        }
+
+        let (packets_tx, packets) = channel();
+        endpoints.insert(
+            addr,
+            Endpoint {
+                packets: packets_tx,
+                waker: None,
+            },
+        );
+
+        Ok(MemoryTransport {
+            addr,
+            network: self.clone(),
+            packets,
+        })
+    }
+
+    fn endpoints(&self) -> MutexGuard<'_, HashMap<SocketAddr, Endpoint>> {
+        // Endpoints are added and removed in one go, a panicking holder can't leave
+        // the map half updated.
+        self.endpoints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+///
+/// Endpoint of a `MemoryNetwork`.
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    packets: Receiver<Packet>,
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn send_to(&self, packet: &[u8], target: SocketAddr) -> io::Result<usize> {
+        // Like UDP, packets to nobody are lost without notice.
+        if let Some(endpoint) = self.network.endpoints().get(&target) {
+            if endpoint.packets.send((self.addr, packet.to_vec())).is_ok() {
+                if let Some(waker) = &endpoint.waker {
+                    waker.wake()?;
+                }
+            }
+        }
+
+        Ok(packet.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.packets.try_recv() {
+            Ok((source, packet)) => {
+                // Like UDP, what doesn't fit into the buffer is cut off.
+                let packet_size = packet.len().min(buf.len());
+                buf[..packet_size].copy_from_slice(&packet[..packet_size]);
+                Ok((packet_size, source))
+            }
+            Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn register(&mut self, _: &Registry, _: Token, waker: Arc<Waker>) -> io::Result<()> {
+        if let Some(endpoint) = self.network.endpoints().get_mut(&self.addr) {
+            endpoint.waker = Some(waker);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.endpoints().remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryNetwork, Transport};
+    use std::io;
+    use std::net::SocketAddr;
+
+    fn roundtrip(a: &dyn Transport, b: &dyn Transport) -> io::Result<(Vec<u8>, SocketAddr)> {
+        a.send_to(b"ping", b.local_addr()?)?;
+
+        let mut buf = [0_u8; 16];
+        // Sockets might not have the packet queued right away.
+        for _ in 0..100 {
+            match b.recv_from(&mut buf) {
+                Ok((packet_size, source)) => return Ok((buf[..packet_size].to_vec(), source)),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::ErrorKind::TimedOut.into())
+    }
+
+    #[test]
+    fn test_packets_reach_their_target() -> io::Result<()> {
+        let a_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
+        let b_addr: SocketAddr = "127.0.0.1:7002".parse().unwrap();
+
+        let network = MemoryNetwork::new();
+        let a = network.bind(a_addr)?;
+        let b = network.bind(b_addr)?;
+        assert!(network.bind(a_addr).is_err());
+        assert_eq!(roundtrip(&a, &b)?, (b"ping".to_vec(), a_addr));
+        drop(b);
+        assert_eq!(a.send_to(b"lost", b_addr)?, 4);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_sockets_reach_their_target() -> io::Result<()> {
+        use super::UnixDatagramTransport;
+
+        let a_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
+        let b_addr: SocketAddr = "127.0.0.1:7002".parse().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("artillery-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        let a = UnixDatagramTransport::bind(&dir, a_addr)?;
+        let b = UnixDatagramTransport::bind(&dir, b_addr)?;
+        assert_eq!(roundtrip(&a, &b)?, (b"ping".to_vec(), a_addr));
+        drop((a, b));
+        std::fs::remove_dir(&dir)
+    }
+}
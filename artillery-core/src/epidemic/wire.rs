@@ -0,0 +1,169 @@
+use super::keyring::{Keyring, ENCRYPTION_OVERHEAD};
+use crate::constants::*;
+use crate::errors::*;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::TryFrom;
+
+// Every epidemic packet and stream frame starts with a single protocol version
+// byte, followed by the bincode encoded message. High bit of the version byte
+// marks packets sealed with the gossip keyring.
+const HEADER_LEN: usize = 1;
+const ENCRYPTED_FLAG: u8 = 0x80;
+
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .with_limit(u64::try_from(CONST_STREAM_FRAME_SIZE).unwrap_or(u64::MAX))
+}
+
+///
+/// Returns `true` if this node can speak the given protocol version.
+pub fn is_supported_version(version: u8) -> bool {
+    (CONST_MIN_PROTOCOL_VERSION..=CONST_PROTOCOL_VERSION).contains(&version)
+}
+
+///
+/// Picks the version to speak with a peer which announced `peer_version`.
+/// Falls back to our own version if the peer's is out of our supported range.
+pub fn negotiate_version(own_version: u8, peer_version: Option<u8>) -> u8 {
+    match peer_version {
+        Some(version) if is_supported_version(version) => own_version.min(version),
+        _ => own_version,
+    }
+}
+
+pub fn encode<T: Serialize>(
+    version: u8,
+    message: &T,
+    keyring: Option<&Keyring>,
+) -> Result<Vec<u8>> {
+    if !is_supported_version(version) {
+        bail!(
+            ArtilleryError::ProtocolVersion,
+            "can't encode with protocol version {}",
+            version
+        );
+    }
+
+    if let Some(ring) = keyring {
+        let header = [version | ENCRYPTED_FLAG];
+        let body = codec().serialize(message)?;
+
+        let mut packet = header.to_vec();
+        packet.extend_from_slice(&ring.encrypt(&header, &body)?);
+        Ok(packet)
+    } else {
+        let mut packet = Vec::with_capacity(HEADER_LEN + encoded_body_len(message)?);
+        packet.push(version);
+        codec().serialize_into(&mut packet, message)?;
+        Ok(packet)
+    }
+}
+
+///
+/// Decodes a packet, returning the protocol version the peer speaks along with the message.
+/// When a keyring is given, only packets sealed with one of its keys are accepted.
+pub fn decode<T: DeserializeOwned>(packet: &[u8], keyring: Option<&Keyring>) -> Result<(u8, T)> {
+    if packet.len() < HEADER_LEN {
+        bail!(ArtilleryError::ClusterMessageDecode, "empty packet");
+    }
+
+    let (header, body) = packet.split_at(HEADER_LEN);
+    let version = header[0] & !ENCRYPTED_FLAG;
+    let encrypted = header[0] & ENCRYPTED_FLAG != 0;
+
+    if !is_supported_version(version) {
+        bail!(
+            ArtilleryError::ProtocolVersion,
+            "peer speaks protocol version {}, supported versions are {}..={}",
+            version,
+            CONST_MIN_PROTOCOL_VERSION,
+            CONST_PROTOCOL_VERSION
+        );
+    }
+
+    let message = match (keyring, encrypted) {
+        (Some(ring), true) => codec().deserialize(&ring.decrypt(header, body)?)?,
+        (None, false) => codec().deserialize(body)?,
+        (Some(_), false) => {
+            bail!(
+                ArtilleryError::Keyring,
+                "plaintext packet received while gossip encryption is enabled"
+            );
+        }
+        (None, true) => {
+            bail!(
+                ArtilleryError::Keyring,
+                "encrypted packet received while gossip encryption is disabled"
+            );
+        }
+    };
+
+    Ok((version, message))
+}
+
+///
+/// Size of the whole packet that `encode` would produce for the message.
+pub fn encoded_len<T: Serialize>(message: &T, keyring: Option<&Keyring>) -> Result<usize> {
+    let overhead = if keyring.is_some() {
+        ENCRYPTION_OVERHEAD
+    } else {
+        0
+    };
+
+    Ok(HEADER_LEN + overhead + encoded_body_len(message)?)
+}
+
+///
+/// Size of the message when it is encoded as a part of another message.
+pub fn encoded_body_len<T: Serialize>(message: &T) -> Result<usize> {
+    Ok(usize::try_from(codec().serialized_size(message)?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode, encoded_len};
+    use crate::epidemic::keyring::Keyring;
+    use crate::epidemic::member::{ArtilleryMember, ArtilleryMemberState};
+    use crate::errors::ArtilleryError;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let member = ArtilleryMember::new(
+            uuid::Uuid::new_v4(),
+            FromStr::from_str("127.0.0.1:1337").unwrap(),
+            123,
+            ArtilleryMemberState::Suspect,
+        );
+
+        let packet = encode(1, &member, None).unwrap();
+        assert_eq!(packet.len(), encoded_len(&member, None).unwrap());
+        assert!(packet.len() < serde_json::to_vec(&member).unwrap().len());
+
+        let (version, decoded): (u8, ArtilleryMember) = decode(&packet, None).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(decoded, member);
+
+        let keyring = Keyring::new(b"cluster", &[]);
+        let packet = encode(1, &member, Some(&keyring)).unwrap();
+        assert_eq!(packet.len(), encoded_len(&member, Some(&keyring)).unwrap());
+        assert!(decode::<ArtilleryMember>(&packet, None).is_err());
+
+        let (_, decoded): (u8, ArtilleryMember) = decode(&packet, Some(&keyring)).unwrap();
+        assert_eq!(decoded, member);
+    }
+
+    #[test]
+    fn test_unsupported_version_is_rejected() {
+        let mut packet = encode(1, &42_u64, None).unwrap();
+        packet[0] = 0x7f;
+
+        match decode::<u64>(&packet, None) {
+            Err(ArtilleryError::ProtocolVersion(_)) => {}
+            other => panic!("Expected a protocol version error, got {:?}", other),
+        }
+    }
+}
@@ -26,6 +26,12 @@ pub enum ArtilleryError {
     Decoding(String),
     #[fail(display = "Artillery :: Numeric Cast Error: {}", _0)]
     NumericCast(String),
+    #[fail(display = "Artillery :: Protocol Version Error: {}", _0)]
+    ProtocolVersion(String),
+    #[fail(display = "Artillery :: Keyring Error: {}", _0)]
+    Keyring(String),
+    #[fail(display = "Artillery :: Configuration Error: {}", _0)]
+    Config(String),
 }
 
 impl From<io::Error> for ArtilleryError {
@@ -40,6 +46,12 @@ impl From<serde_json::error::Error> for ArtilleryError {
     }
 }
 
+impl From<bincode::Error> for ArtilleryError {
+    fn from(e: bincode::Error) -> Self {
+        ArtilleryError::ClusterMessageDecode(e.to_string())
+    }
+}
+
 impl<T> From<std::sync::mpsc::SendError<T>> for ArtilleryError {
     fn from(e: SendError<T>) -> Self {
         ArtilleryError::Send(e.to_string())
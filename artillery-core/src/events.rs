@@ -0,0 +1,239 @@
+use crossbeam_channel::{bounded, unbounded, Iter, Receiver, RecvError, RecvTimeoutError};
+use crossbeam_channel::{SendError, Sender, TryIter, TryRecvError, TrySendError};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+type Wakers = Arc<Mutex<Vec<Waker>>>;
+
+///
+/// Creates an unbounded event channel, which can be consumed both by blocking
+/// iteration and as a [`Stream`].
+pub fn event_channel<T>() -> (EventSender<T>, EventReceiver<T>) {
+    let (tx, rx) = unbounded();
+
+    channel_of(tx, rx, false)
+}
+
+///
+/// Creates an event channel holding at most `capacity` events. Its sender can make
+/// room by evicting the oldest event, see [`EventSender::evict_oldest`].
+pub fn bounded_event_channel<T>(capacity: usize) -> (EventSender<T>, EventReceiver<T>) {
+    let (tx, rx) = bounded(capacity);
+
+    channel_of(tx, rx, true)
+}
+
+fn channel_of<T>(
+    tx: Sender<T>,
+    rx: Receiver<T>,
+    evictable: bool,
+) -> (EventSender<T>, EventReceiver<T>) {
+    let wakers = Wakers::default();
+    let alive = Arc::new(());
+
+    (
+        EventSender {
+            tx: Some(tx),
+            evict: if evictable { Some(rx.clone()) } else { None },
+            receivers: Arc::downgrade(&alive),
+            wakers: wakers.clone(),
+        },
+        EventReceiver { rx, wakers, alive },
+    )
+}
+
+#[derive(Debug)]
+pub struct EventSender<T> {
+    // Only taken on drop, so that the disconnection is visible before the wakeup.
+    tx: Option<Sender<T>>,
+    // Receiving end kept to evict from, it keeps the channel itself open.
+    evict: Option<Receiver<T>>,
+    receivers: Weak<()>,
+    wakers: Wakers,
+}
+
+impl<T> EventSender<T> {
+    pub fn send(&self, event: T) -> Result<(), SendError<T>> {
+        let tx = match &self.tx {
+            Some(tx) if self.is_connected() => tx,
+            Some(_) | None => return Err(SendError(event)),
+        };
+
+        tx.send(event)?;
+        wake_all(&self.wakers);
+
+        Ok(())
+    }
+
+    ///
+    /// Sends without blocking, fails if the channel is full or every receiver is gone.
+    pub fn try_send(&self, event: T) -> Result<(), TrySendError<T>> {
+        let tx = match &self.tx {
+            Some(tx) if self.is_connected() => tx,
+            Some(_) | None => return Err(TrySendError::Disconnected(event)),
+        };
+
+        tx.try_send(event)?;
+        wake_all(&self.wakers);
+
+        Ok(())
+    }
+
+    ///
+    /// Takes the oldest queued event out of a bounded channel.
+    pub fn evict_oldest(&self) -> Option<T> {
+        self.evict.as_ref().and_then(|rx| rx.try_recv().ok())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.receivers.strong_count() > 0
+    }
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        EventSender {
+            tx: self.tx.clone(),
+            evict: self.evict.clone(),
+            receivers: self.receivers.clone(),
+            wakers: self.wakers.clone(),
+        }
+    }
+}
+
+impl<T> Drop for EventSender<T> {
+    fn drop(&mut self) {
+        // Pending consumers have to notice the disconnection.
+        drop(self.tx.take());
+        wake_all(&self.wakers);
+    }
+}
+
+///
+/// Receiving side of an event channel.
+///
+/// Clones share the same channel, so every event is delivered to exactly one of them.
+#[derive(Debug)]
+pub struct EventReceiver<T> {
+    rx: Receiver<T>,
+    wakers: Wakers,
+    // Lets the senders notice that every receiver is gone.
+    alive: Arc<()>,
+}
+
+impl<T> EventReceiver<T> {
+    ///
+    /// Blocking iterator over the events, ends when the sender is gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.rx.iter()
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        self.rx.try_iter()
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.rx.recv()
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    ///
+    /// Polls for the next event, registering the task for a wakeup if there is none.
+    /// Returns `None` once the sender is gone and the channel is drained.
+    pub fn poll_event(&self, cx: &mut Context) -> Poll<Option<T>> {
+        match self.rx.try_recv() {
+            Ok(event) => return Poll::Ready(Some(event)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        if let Ok(mut wakers) = self.wakers.lock() {
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        // Event might have arrived before the waker was registered.
+        match self.rx.try_recv() {
+            Ok(event) => Poll::Ready(Some(event)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Clone for EventReceiver<T> {
+    fn clone(&self) -> Self {
+        EventReceiver {
+            rx: self.rx.clone(),
+            wakers: self.wakers.clone(),
+            alive: self.alive.clone(),
+        }
+    }
+}
+
+impl<T> Stream for EventReceiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.poll_event(cx)
+    }
+}
+
+fn wake_all(wakers: &Wakers) {
+    if let Ok(mut registered) = wakers.lock() {
+        for waker in registered.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bounded_event_channel, event_channel};
+    use futures::executor::block_on;
+    use futures::StreamExt;
+    use std::thread;
+
+    #[test]
+    fn test_stream_wakes_up_on_events() {
+        let (tx, mut rx) = event_channel();
+
+        let producer = thread::spawn(move || {
+            for event in 0..3 {
+                tx.send(event).unwrap();
+            }
+        });
+
+        let events: Vec<u32> = block_on(async { (&mut rx).collect().await });
+        producer.join().unwrap();
+
+        assert_eq!(events, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_bounded_channel_evicts_and_notices_dropped_receivers() {
+        let (tx, rx) = bounded_event_channel(2);
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(tx.try_send(3).is_err());
+        assert_eq!(tx.evict_oldest(), Some(1));
+        tx.try_send(3).unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<u32>>(), vec![2, 3]);
+
+        drop(rx);
+        assert!(!tx.is_connected());
+        assert!(tx.try_send(4).is_err());
+    }
+}
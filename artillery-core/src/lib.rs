@@ -70,9 +70,15 @@ pub mod errors;
 /// Constants of the Artillery
 pub mod constants;
 
+/// Event channels of the cluster and the service discovery
+pub mod events;
+
 /// Infection-style clustering
 pub mod epidemic;
 
+/// Metrics of the epidemic and the service discovery
+pub mod metrics;
+
 /// Service discovery strategies
 pub mod service_discovery;
 
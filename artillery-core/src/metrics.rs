@@ -0,0 +1,517 @@
+use bastion_executor::blocking::spawn_blocking;
+use lightproc::proc_stack::ProcStack;
+use serde::*;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Upper bounds of the packet size buckets, in bytes.
+const PACKET_SIZE_BUCKETS: [u64; 6] = [64, 128, 256, 512, 1024, 1400];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+///
+/// Distribution of observed values over fixed buckets.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<u64>,
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[u64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        if let Some(bucket) = self
+            .bounds
+            .iter()
+            .zip(&self.buckets)
+            .find(|(bound, _)| value <= **bound)
+            .map(|(_, bucket)| bucket)
+        {
+            bucket.fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, bucket)| {
+                cumulative += bucket.load(Ordering::Relaxed);
+                (*bound, cumulative)
+            })
+            .collect();
+
+        HistogramSnapshot {
+            buckets,
+            sum: self.sum.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+///
+/// Histogram at a point in time, buckets hold the cumulative count up to their bound.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<(u64, u64)>,
+    pub sum: u64,
+    pub count: u64,
+}
+
+///
+/// Registry updated by the epidemic and the service discovery event loops. Share one
+/// through their configs to collect both in the same place.
+#[derive(Debug)]
+pub struct Metrics {
+    /// Direct probes sent
+    pub probes: Counter,
+    /// Indirect probes requested from other members
+    pub indirect_probes: Counter,
+    /// Members which became suspected
+    pub suspicions: Counter,
+    /// Members which were confirmed down
+    pub failures: Counter,
+    /// Members which came back alive
+    pub recoveries: Counter,
+    /// Suspicions about ourselves we refuted
+    pub refutations: Counter,
+    pub packets_sent: Counter,
+    pub packets_received: Counter,
+    /// Outgoing messages dropped because they couldn't be built or sent
+    pub dropped_sends: Counter,
+    /// Incoming gossip packets which couldn't be decoded
+    pub decode_failures: Counter,
+    /// Incoming gossip packets dropped because their source is quarantined
+    pub quarantined_packets: Counter,
+    /// Incoming gossip packets which couldn't be received or queued
+    pub receive_errors: Counter,
+    /// Cluster events which didn't fit into the queue of a subscriber
+    pub dropped_events: Counter,
+    pub sent_packet_sizes: Histogram,
+    pub received_packet_sizes: Histogram,
+    /// State changes waiting to be piggybacked
+    pub piggyback_queue_depth: Gauge,
+    /// Broadcasts waiting to be piggybacked
+    pub broadcast_queue_depth: Gauge,
+    pub alive_members: Gauge,
+    pub suspect_members: Gauge,
+    /// Sources whose packets are dropped for sending too many bad ones
+    pub quarantined_sources: Gauge,
+    /// Peer searches sent by the service discovery
+    pub discovery_seeks: Counter,
+    /// Peer searches received by the service discovery
+    pub discovery_requests: Counter,
+    pub discovery_replies_sent: Counter,
+    pub discovery_replies_received: Counter,
+    /// Service discovery packets which couldn't be decoded
+    pub discovery_decode_failures: Counter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            probes: Counter::default(),
+            indirect_probes: Counter::default(),
+            suspicions: Counter::default(),
+            failures: Counter::default(),
+            recoveries: Counter::default(),
+            refutations: Counter::default(),
+            packets_sent: Counter::default(),
+            packets_received: Counter::default(),
+            dropped_sends: Counter::default(),
+            decode_failures: Counter::default(),
+            quarantined_packets: Counter::default(),
+            receive_errors: Counter::default(),
+            dropped_events: Counter::default(),
+            sent_packet_sizes: Histogram::new(&PACKET_SIZE_BUCKETS),
+            received_packet_sizes: Histogram::new(&PACKET_SIZE_BUCKETS),
+            piggyback_queue_depth: Gauge::default(),
+            broadcast_queue_depth: Gauge::default(),
+            alive_members: Gauge::default(),
+            suspect_members: Gauge::default(),
+            quarantined_sources: Gauge::default(),
+            discovery_seeks: Counter::default(),
+            discovery_requests: Counter::default(),
+            discovery_replies_sent: Counter::default(),
+            discovery_replies_received: Counter::default(),
+            discovery_decode_failures: Counter::default(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            probes: self.probes.get(),
+            indirect_probes: self.indirect_probes.get(),
+            suspicions: self.suspicions.get(),
+            failures: self.failures.get(),
+            recoveries: self.recoveries.get(),
+            refutations: self.refutations.get(),
+            packets_sent: self.packets_sent.get(),
+            packets_received: self.packets_received.get(),
+            dropped_sends: self.dropped_sends.get(),
+            decode_failures: self.decode_failures.get(),
+            quarantined_packets: self.quarantined_packets.get(),
+            receive_errors: self.receive_errors.get(),
+            dropped_events: self.dropped_events.get(),
+            sent_packet_sizes: self.sent_packet_sizes.snapshot(),
+            received_packet_sizes: self.received_packet_sizes.snapshot(),
+            piggyback_queue_depth: self.piggyback_queue_depth.get(),
+            broadcast_queue_depth: self.broadcast_queue_depth.get(),
+            alive_members: self.alive_members.get(),
+            suspect_members: self.suspect_members.get(),
+            quarantined_sources: self.quarantined_sources.get(),
+            discovery_seeks: self.discovery_seeks.get(),
+            discovery_requests: self.discovery_requests.get(),
+            discovery_replies_sent: self.discovery_replies_sent.get(),
+            discovery_replies_received: self.discovery_replies_received.get(),
+            discovery_decode_failures: self.discovery_decode_failures.get(),
+        }
+    }
+}
+
+///
+/// Values of the [`Metrics`] at a point in time.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub probes: u64,
+    pub indirect_probes: u64,
+    pub suspicions: u64,
+    pub failures: u64,
+    pub recoveries: u64,
+    pub refutations: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub dropped_sends: u64,
+    pub decode_failures: u64,
+    pub quarantined_packets: u64,
+    pub receive_errors: u64,
+    pub dropped_events: u64,
+    pub sent_packet_sizes: HistogramSnapshot,
+    pub received_packet_sizes: HistogramSnapshot,
+    pub piggyback_queue_depth: u64,
+    pub broadcast_queue_depth: u64,
+    pub alive_members: u64,
+    pub suspect_members: u64,
+    pub quarantined_sources: u64,
+    pub discovery_seeks: u64,
+    pub discovery_requests: u64,
+    pub discovery_replies_sent: u64,
+    pub discovery_replies_received: u64,
+    pub discovery_decode_failures: u64,
+}
+
+impl MetricsSnapshot {
+    ///
+    /// Renders the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        counter(&mut out, "probes", "Direct probes sent", self.probes);
+        counter(
+            &mut out,
+            "indirect_probes",
+            "Indirect probes requested from other members",
+            self.indirect_probes,
+        );
+        counter(
+            &mut out,
+            "suspicions",
+            "Members which became suspected",
+            self.suspicions,
+        );
+        counter(
+            &mut out,
+            "failures",
+            "Members which were confirmed down",
+            self.failures,
+        );
+        counter(
+            &mut out,
+            "recoveries",
+            "Members which came back alive",
+            self.recoveries,
+        );
+        counter(
+            &mut out,
+            "refutations",
+            "Suspicions about this member it refuted",
+            self.refutations,
+        );
+        counter(
+            &mut out,
+            "packets_sent",
+            "Gossip packets sent",
+            self.packets_sent,
+        );
+        counter(
+            &mut out,
+            "packets_received",
+            "Gossip packets received",
+            self.packets_received,
+        );
+        counter(
+            &mut out,
+            "dropped_sends",
+            "Outgoing messages dropped",
+            self.dropped_sends,
+        );
+        counter(
+            &mut out,
+            "decode_failures",
+            "Gossip packets which couldn't be decoded",
+            self.decode_failures,
+        );
+        counter(
+            &mut out,
+            "quarantined_packets",
+            "Gossip packets dropped because their source is quarantined",
+            self.quarantined_packets,
+        );
+        counter(
+            &mut out,
+            "receive_errors",
+            "Gossip packets which couldn't be received or queued",
+            self.receive_errors,
+        );
+        counter(
+            &mut out,
+            "dropped_events",
+            "Cluster events which didn't fit into the queue of a subscriber",
+            self.dropped_events,
+        );
+        histogram(
+            &mut out,
+            "sent_packet_bytes",
+            "Size of the gossip packets sent",
+            &self.sent_packet_sizes,
+        );
+        histogram(
+            &mut out,
+            "received_packet_bytes",
+            "Size of the gossip packets received",
+            &self.received_packet_sizes,
+        );
+        gauge(
+            &mut out,
+            "piggyback_queue_depth",
+            "State changes waiting to be piggybacked",
+            self.piggyback_queue_depth,
+        );
+        gauge(
+            &mut out,
+            "broadcast_queue_depth",
+            "Broadcasts waiting to be piggybacked",
+            self.broadcast_queue_depth,
+        );
+        gauge(
+            &mut out,
+            "alive_members",
+            "Members considered alive",
+            self.alive_members,
+        );
+        gauge(
+            &mut out,
+            "suspect_members",
+            "Members currently suspected",
+            self.suspect_members,
+        );
+        gauge(
+            &mut out,
+            "quarantined_sources",
+            "Sources quarantined for sending bad packets",
+            self.quarantined_sources,
+        );
+        counter(
+            &mut out,
+            "discovery_seeks",
+            "Peer searches sent by the service discovery",
+            self.discovery_seeks,
+        );
+        counter(
+            &mut out,
+            "discovery_requests",
+            "Peer searches received by the service discovery",
+            self.discovery_requests,
+        );
+        counter(
+            &mut out,
+            "discovery_replies_sent",
+            "Service discovery replies sent",
+            self.discovery_replies_sent,
+        );
+        counter(
+            &mut out,
+            "discovery_replies_received",
+            "Service discovery replies received",
+            self.discovery_replies_received,
+        );
+        counter(
+            &mut out,
+            "discovery_decode_failures",
+            "Service discovery packets which couldn't be decoded",
+            self.discovery_decode_failures,
+        );
+
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP artillery_{}_total {}", name, help);
+    let _ = writeln!(out, "# TYPE artillery_{}_total counter", name);
+    let _ = writeln!(out, "artillery_{}_total {}", name, value);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP artillery_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE artillery_{} gauge", name);
+    let _ = writeln!(out, "artillery_{} {}", name, value);
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, value: &HistogramSnapshot) {
+    let _ = writeln!(out, "# HELP artillery_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE artillery_{} histogram", name);
+    for (bound, count) in &value.buckets {
+        let _ = writeln!(
+            out,
+            "artillery_{}_bucket{{le=\"{}\"}} {}",
+            name, bound, count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "artillery_{}_bucket{{le=\"+Inf\"}} {}",
+        name, value.count
+    );
+    let _ = writeln!(out, "artillery_{}_sum {}", name, value.sum);
+    let _ = writeln!(out, "artillery_{}_count {}", name, value.count);
+}
+
+///
+/// Serves the metrics over HTTP to Prometheus until `running` is cleared and the
+/// listener is woken up.
+pub(crate) fn spawn_exporter(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    timeout: Duration,
+    running: Arc<AtomicBool>,
+) {
+    let _exporter_handle = spawn_blocking(
+        async move {
+            for incoming in listener.incoming() {
+                if !running.load(Ordering::SeqCst) {
+                    debug!("Stopping artillery metrics exporter");
+                    break;
+                }
+
+                match incoming {
+                    Ok(stream) => {
+                        if let Err(e) = serve_scrape(stream, &metrics, timeout) {
+                            debug!("Metrics scrape failed: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to accept metrics scrape: {}", e),
+                }
+            }
+        },
+        ProcStack::default(),
+    );
+}
+
+fn serve_scrape(stream: TcpStream, metrics: &Metrics, timeout: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Every path is answered with the metrics, only the request head is consumed.
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line.trim_end() != "" {
+        line.clear();
+    }
+
+    let body = metrics.snapshot().to_prometheus();
+    let mut connection = reader.into_inner();
+    write!(
+        connection,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+
+    connection.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Metrics;
+
+    #[test]
+    fn test_snapshot_renders_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.probes.add(3);
+        metrics.alive_members.set(5);
+        for size in &[100, 1200, 9000] {
+            metrics.sent_packet_sizes.observe(*size);
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.probes, 3);
+        assert_eq!(snapshot.sent_packet_sizes.count, 3);
+        assert_eq!(snapshot.sent_packet_sizes.sum, 10300);
+
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("artillery_probes_total 3\n"));
+        assert!(text.contains("artillery_alive_members 5\n"));
+        assert!(text.contains("artillery_sent_packet_bytes_bucket{le=\"128\"} 1\n"));
+        assert!(text.contains("artillery_sent_packet_bytes_bucket{le=\"1400\"} 2\n"));
+        assert!(text.contains("artillery_sent_packet_bytes_bucket{le=\"+Inf\"} 3\n"));
+    }
+}
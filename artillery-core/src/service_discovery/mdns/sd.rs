@@ -8,15 +8,14 @@ use libp2p::multiaddr::Protocol;
 use libp2p::{identity, Multiaddr, PeerId};
 use lightproc::proc_stack::ProcStack;
 
-use crossbeam_channel::{unbounded, Receiver};
+use crate::events::{event_channel, EventReceiver};
+use futures::Stream;
 use kaos::flunk;
-use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::task::{Context, Poll};
 
 pub struct MDNSServiceDiscovery {
-    events: Arc<Receiver<MDNSServiceDiscoveryEvent>>,
+    events: EventReceiver<MDNSServiceDiscoveryEvent>,
 }
 
 unsafe impl Send for MDNSServiceDiscovery {}
@@ -24,7 +23,7 @@ unsafe impl Sync for MDNSServiceDiscovery {}
 
 impl MDNSServiceDiscovery {
     pub fn new_service_discovery(config: MDNSServiceDiscoveryConfig) -> Result<Self> {
-        let (event_tx, event_rx) = unbounded::<MDNSServiceDiscoveryEvent>();
+        let (event_tx, event_rx) = event_channel::<MDNSServiceDiscoveryEvent>();
 
         let peer_id = PeerId::from(identity::Keypair::generate_ed25519().public());
 
@@ -99,23 +98,21 @@ impl MDNSServiceDiscovery {
             ProcStack::default(),
         );
 
-        Ok(Self {
-            events: Arc::new(event_rx),
-        })
+        Ok(Self { events: event_rx })
     }
 
-    pub fn events(&self) -> Arc<Receiver<MDNSServiceDiscoveryEvent>> {
+    ///
+    /// Discovered services, both as a blocking iterator and as a [`Stream`].
+    /// Handles share the same channel, every discovery is seen by one of them.
+    pub fn events(&self) -> EventReceiver<MDNSServiceDiscoveryEvent> {
         self.events.clone()
     }
 }
 
-impl Future for MDNSServiceDiscovery {
-    type Output = MDNSServiceDiscoveryEvent;
+impl Stream for MDNSServiceDiscovery {
+    type Item = MDNSServiceDiscoveryEvent;
 
-    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Self::Output> {
-        match self.events.recv() {
-            Ok(kv) => Poll::Ready(kv),
-            Err(_) => Poll::Pending,
-        }
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.events.poll_event(cx)
     }
 }
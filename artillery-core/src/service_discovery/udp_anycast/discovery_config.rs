@@ -1,11 +1,15 @@
 use crate::constants::*;
+use crate::metrics::Metrics;
 use chrono::Duration;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 
 pub struct MulticastServiceDiscoveryConfig {
     pub timeout_delta: Duration,
     pub seeking_addr: SocketAddr,
     pub discovery_addr: SocketAddr,
+    /// Registry the discovery records its metrics into
+    pub metrics: Arc<Metrics>,
 }
 
 impl Default for MulticastServiceDiscoveryConfig {
@@ -17,6 +21,7 @@ impl Default for MulticastServiceDiscoveryConfig {
             timeout_delta: Duration::seconds(1),
             seeking_addr: seeking_addr.to_socket_addrs().unwrap().next().unwrap(),
             discovery_addr: discovery_addr.to_socket_addrs().unwrap().next().unwrap(),
+            metrics: Arc::new(Metrics::default()),
         }
     }
 }
@@ -1,4 +1,5 @@
 use crate::errors::*;
+use crate::metrics::{Metrics, MetricsSnapshot};
 use crate::service_discovery::udp_anycast::discovery_config::MulticastServiceDiscoveryConfig;
 use crate::service_discovery::udp_anycast::state::MulticastServiceDiscoveryState;
 use crate::service_discovery::udp_anycast::state::{
@@ -9,9 +10,11 @@ use cuneiform_fields::arch::ArchPadding;
 use lightproc::proc_stack::ProcStack;
 use std::sync::mpsc;
 use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
 
 pub struct MulticastServiceDiscovery {
     comm: ArchPadding<Sender<ServiceDiscoveryRequest>>,
+    metrics: Arc<Metrics>,
 }
 
 impl MulticastServiceDiscovery {
@@ -20,6 +23,7 @@ impl MulticastServiceDiscovery {
         discovery_reply: ServiceDiscoveryReply,
     ) -> Result<Self> {
         let (internal_tx, mut internal_rx) = channel::<ServiceDiscoveryRequest>();
+        let metrics = config.metrics.clone();
         let (poll, state) = MulticastServiceDiscoveryState::new(config, discovery_reply)?;
 
         debug!("Starting Artillery Multicast SD");
@@ -33,6 +37,7 @@ impl MulticastServiceDiscovery {
 
         Ok(Self {
             comm: ArchPadding::new(internal_tx),
+            metrics,
         })
     }
 
@@ -58,6 +63,11 @@ impl MulticastServiceDiscovery {
         Ok(self.comm.send(ServiceDiscoveryRequest::SeekPeers)?)
     }
 
+    /// Current values of the metrics recorded by the discovery.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
     /// Shutdown Service Discovery
     pub fn shutdown(&mut self) -> Result<()> {
         self.discovery_exit();
@@ -99,16 +99,24 @@ impl MulticastServiceDiscoveryState {
 
     fn readable(&mut self, buf: &mut [u8], poll: &mut Poll) -> Result<()> {
         if let Ok((_bytes_read, peer_addr)) = self.server_socket.recv_from(buf) {
-            let serialized = std::str::from_utf8(buf)?.to_string().trim().to_string();
+            let serialized = match std::str::from_utf8(buf) {
+                Ok(serialized) => serialized.trim().to_string(),
+                Err(e) => {
+                    self.config.metrics.discovery_decode_failures.inc();
+                    return Err(e.into());
+                }
+            };
             let serialized = serialized.trim_matches(char::from(0x00));
             let msg: ServiceDiscoveryMessage = if let Ok(msg) = serde_json::from_str(serialized) {
                 msg
             } else {
+                self.config.metrics.discovery_decode_failures.inc();
                 return Ok(());
             };
 
             match msg {
                 ServiceDiscoveryMessage::Request => {
+                    self.config.metrics.discovery_requests.inc();
                     if self.listen {
                         self.seeker_replies.push_back(peer_addr);
                         poll.registry().reregister(
@@ -126,6 +134,7 @@ impl MulticastServiceDiscoveryState {
                 }
                 ServiceDiscoveryMessage::Response { uid, content } => {
                     if uid != self.uid {
+                        self.config.metrics.discovery_replies_received.inc();
                         self.observers
                             .retain(|observer| observer.send(content.clone()).is_ok());
                     }
@@ -168,6 +177,7 @@ impl MulticastServiceDiscoveryState {
                             return Ok(());
                         }
                     }
+                    self.config.metrics.discovery_replies_sent.inc();
                 }
             }
             SEEK_NODES => {
@@ -188,6 +198,7 @@ impl MulticastServiceDiscoveryState {
                         return Ok(());
                     }
                 }
+                self.config.metrics.discovery_seeks.inc();
             }
             _ => (),
         }
@@ -281,6 +292,7 @@ impl MulticastServiceDiscoveryState {
                     .send_to(&self.seek_request, self.config.seeking_addr)
                 {
                     Ok(_) => {
+                        self.config.metrics.discovery_seeks.inc();
                         if let Err(err) = poll.registry().reregister(
                             &mut self.server_socket,
                             ON_DISCOVERY,